@@ -8,12 +8,13 @@ use handler::*;
 
 use serenity::{framework::StandardFramework, prelude::*};
 use serenity_ctrlc::Ext;
+use ws_protocol::WsTask;
 
 type SetupFn = Box<
     dyn FnOnce(
         discord_config::DiscordGameSetupData,
         &mut serenity::prelude::TypeMap,
-        &mut discord_config::Canceller,
+        &mut ws_protocol::WsConnection,
     ) -> Result<(), ()>,
 >;
 
@@ -58,25 +59,34 @@ impl Bot {
             .await?;
 
         let mut canceller = discord_config::Canceller(vec![]);
+        let mut connection = ws_protocol::WsConnection::new();
+        let mut configured = false;
 
         {
             let mut data = client.data.write().await;
 
             self.game_setups.into_iter().for_each(|(game, setup)| {
-                setup(
+                match setup(
                     discord_config::DiscordGameSetupData {
                         config_path: String::from(CONFIG_PATH),
                         data: client.data.clone(),
                         cache_and_http: client.cache_and_http.clone(),
-                        addr: addr.clone(),
                     },
                     &mut data,
-                    &mut canceller,
-                )
-                .unwrap_or_else(|_| println!("Failed setup for {game}, skipping."));
+                    &mut connection,
+                ) {
+                    Ok(()) => configured = true,
+                    Err(()) => println!("Failed setup for {game}, skipping."),
+                }
             });
         }
 
+        if configured {
+            let (runner, ws_canceller) = connection.build();
+            canceller.add(ws_canceller);
+            tokio::spawn(runner.run(addr));
+        }
+
         let canceller = Arc::new(Mutex::new(Some(canceller)));
         let mut client = client
             .ctrlc_with(move |dc| {
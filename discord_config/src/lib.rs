@@ -97,7 +97,6 @@ pub struct DiscordGameSetupData {
     pub config_path: String,
     pub data: Arc<tokio::sync::RwLock<serenity::prelude::TypeMap>>,
     pub cache_and_http: Arc<serenity::CacheAndHttp>,
-    pub addr: String,
 }
 
 #[async_trait]
@@ -107,7 +106,7 @@ pub trait DiscordGame {
     fn setup(
         setup_data: DiscordGameSetupData,
         serenity_data: &mut serenity::prelude::TypeMap,
-        canceller: &mut Canceller,
+        connection: &mut ws_protocol::WsConnection,
     ) -> Result<(), ()>;
     async fn has_necessary_permissions(
         &self,
@@ -129,8 +128,9 @@ pub trait BjornMessageHandler {
     async fn server_message(
         data: Arc<tokio::sync::RwLock<serenity::prelude::TypeMap>>,
         cache_and_http: Arc<serenity::CacheAndHttp>,
+        instance: Option<String>,
         message: <<Self::Handler as ClientApiHandler>::Api as ClientApi>::Message,
-    );
+    ) -> Result<(), serenity::Error>;
 }
 
 // I hate the way $mut works here, but I couldn't figure out a better way to make it work.
@@ -138,6 +138,7 @@ pub struct Api;
 
 impl ws_protocol::ClientApi for Api {
     type Message = Message;
+    type Reply = ();
 
     fn id() -> &'static str {
         "minecraft_client"
@@ -145,6 +146,7 @@ impl ws_protocol::ClientApi for Api {
 }
 
 pub struct Handler {
+    instance: Option<String>,
     data: Arc<tokio::sync::RwLock<serenity::prelude::TypeMap>>,
     cache_and_http: Arc<serenity::CacheAndHttp>,
 }
@@ -153,23 +155,32 @@ impl Handler {
     pub fn new(
         data: Arc<tokio::sync::RwLock<serenity::prelude::TypeMap>>,
         cache_and_http: Arc<serenity::CacheAndHttp>,
+        instance: Option<String>,
     ) -> Handler {
         Handler {
+            instance,
             data,
             cache_and_http,
         }
     }
 }
 
+#[serenity::async_trait]
 impl ws_protocol::ClientApiHandler for Handler {
     type Api = Api;
+    type Error = serenity::Error;
 
-    fn handle_message(&mut self, message: Message) {
-        // TODO: I should think of a better way to do this.
-        tokio::spawn(MessageHandler::server_message(
+    fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+
+    async fn handle_message(&mut self, message: Message) -> Result<(), Self::Error> {
+        MessageHandler::server_message(
             self.data.clone(),
             self.cache_and_http.clone(),
+            self.instance.clone(),
             message,
-        ));
+        )
+        .await
     }
 }
@@ -0,0 +1,56 @@
+use std::{
+    collections::HashMap,
+    env::{self, VarError},
+};
+
+pub fn instances() -> Vec<Option<String>> {
+    let mut suffixes = HashMap::new();
+    match env::var("BJORN_MINECRAFT_INSTANCES") {
+        Ok(names) => names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            // Checked here so an invalid name can't reach ws_protocol::target later on.
+            .filter(|name| {
+                let valid = ws_protocol::is_valid_instance(name);
+                if !valid {
+                    println!(
+                        "Ignoring invalid instance name \"{name}\" in BJORN_MINECRAFT_INSTANCES: use letters, digits, '-' or '_'."
+                    );
+                }
+                valid
+            })
+            // Names that only differ in case or '-' against '_' would read the same variables
+            // and quietly share a server, so only the first of them is kept.
+            .filter(|name| match suffixes.get(&var_suffix(name)) {
+                Some(first) => {
+                    println!(
+                        "Ignoring instance name \"{name}\" in BJORN_MINECRAFT_INSTANCES: it reads the same variables as \"{first}\"."
+                    );
+                    false
+                }
+                None => {
+                    suffixes.insert(var_suffix(name), name.to_string());
+                    true
+                }
+            })
+            .map(|name| Some(name.into()))
+            .collect(),
+        Err(_) => vec![None],
+    }
+}
+
+pub fn instance_var(key: &str, instance: Option<&str>) -> Result<String, VarError> {
+    match instance {
+        Some(instance) => env::var(format!("{key}_{}", var_suffix(instance))),
+        None => env::var(key),
+    }
+}
+
+pub fn var(key: &str, instance: Option<&str>) -> Result<String, VarError> {
+    instance_var(key, instance).or_else(|_| env::var(key))
+}
+
+fn var_suffix(instance: &str) -> String {
+    instance.to_uppercase().replace('-', "_")
+}
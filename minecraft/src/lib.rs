@@ -1,3 +1,5 @@
+pub mod instance;
+
 pub mod server;
 
 // TODO: I need to make this not dependent on serenity.
@@ -5,7 +5,6 @@ use serenity::{
     async_trait, framework::standard::macros::group, http::Typing, model::prelude::Message,
     prelude::*,
 };
-use ws_protocol::WsTask;
 
 use super::*;
 
@@ -25,27 +24,31 @@ impl discord_config::BjornMessageHandler for MessageHandler {
 
     async fn client_message(ctx: &Context, msg: &Message) {
         let data = ctx.data.read().await;
-        let api = data
-            .get::<ws_protocol::WsClient<server::Api>>()
-            .unwrap()
-            .lock()
-            .unwrap();
-        api.send(server::Message::Chat(
-            msg.author.name.clone(),
-            msg.content.replace("\n", " "),
-        ));
+        let apis = data.get::<ServerApis>().unwrap().lock().unwrap();
+
+        for api in apis.all() {
+            api.send(server::Message::Chat(
+                msg.author.name.clone(),
+                msg.content.replace("\n", " "),
+            ));
+        }
     }
 
     async fn server_message(
         data: Arc<tokio::sync::RwLock<TypeMap>>,
         http_and_cache: Arc<serenity::CacheAndHttp>,
+        instance: Option<String>,
         message: client::Message,
-    ) {
+    ) -> Result<(), serenity::Error> {
         let (has_follow_up, message_text) = {
             let data = data.read().await;
             let players = data.get::<Players>().unwrap().lock().unwrap();
+            let message_text = match &instance {
+                Some(instance) => format!("[{instance}] {}", message.to_string(&players)),
+                None => message.to_string(&players),
+            };
 
-            (message.indicates_follow_up(), message.to_string(&players))
+            (message.indicates_follow_up(), message_text)
         };
 
         if let client::Message::Command(player, command, target) = message {
@@ -71,16 +74,17 @@ impl discord_config::BjornMessageHandler for MessageHandler {
 
             if let Some(message) = message {
                 let data = data.read().await;
-                let server_api = data
-                    .get::<ws_protocol::WsClient<server::Api>>()
-                    .unwrap()
-                    .lock()
-                    .unwrap();
-                server_api.send(message);
+                let apis = data.get::<ServerApis>().unwrap().lock().unwrap();
+
+                if let Ok(server_api) = apis.get(instance.as_deref()) {
+                    server_api.send(message);
+                }
             }
         }
 
         use_data!(data, |config: DiscordConfig| {
+            let mut result = Ok(());
+
             if !config.suppress_server_messages {
                 let mut typing_results = vec![];
                 for channel in &config.chat_channels {
@@ -106,7 +110,7 @@ impl discord_config::BjornMessageHandler for MessageHandler {
                         .await;
 
                     if let Err(e) = message_result {
-                        dbg!("Error sending message to Discord channel:", e);
+                        result = Err(e);
                     }
 
                     if has_follow_up {
@@ -133,7 +137,9 @@ impl discord_config::BjornMessageHandler for MessageHandler {
                     }
                 }
             }
-        });
+
+            result
+        })
     }
 }
 
@@ -150,13 +156,12 @@ impl discord_config::DiscordGame for DiscordConfig {
     fn setup(
         setup_data: discord_config::DiscordGameSetupData,
         serenity_data: &mut serenity::prelude::TypeMap,
-        canceller: &mut discord_config::Canceller,
+        connection: &mut ws_protocol::WsConnection,
     ) -> Result<(), ()> {
         let discord_config::DiscordGameSetupData {
             config_path,
             data,
             cache_and_http,
-            addr,
         } = setup_data;
 
         let config = match discord_config::load_config(&config_path) {
@@ -166,14 +171,29 @@ impl discord_config::DiscordGame for DiscordConfig {
 
         serenity_data.insert::<DiscordConfig>(Mutex::new(Some(config)));
 
-        let (server_api, runner, out_canceller) = ws_protocol::WsClient::<server::Api>::new();
-        let (client_handler, in_canceller) =
-            ws_protocol::WsClientHandler::new(client::Handler::new(data, cache_and_http));
-
-        canceller.add(out_canceller);
-        canceller.add(in_canceller);
+        let mut server_apis = ServerApis::default();
+        for instance in crate::instance::instances() {
+            // Commands come from someone waiting on a reply, so they're refused while the game
+            // manager is away rather than run long after they were sent, as Valheim's are.
+            let server_api = match connection.instance_client_with_policy::<server::Api>(
+                instance.as_deref(),
+                ws_protocol::OutboxPolicy::Reject,
+            ) {
+                Ok(server_api) => server_api,
+                Err(e) => {
+                    println!("Skipping Minecraft instance: {e}");
+                    continue;
+                }
+            };
+            server_apis.insert(instance.clone(), server_api);
+            connection.handler(client::Handler::new(
+                data.clone(),
+                cache_and_http.clone(),
+                instance,
+            ));
+        }
 
-        serenity_data.insert::<ws_protocol::WsClient<server::Api>>(Mutex::new(server_api));
+        serenity_data.insert::<ServerApis>(Mutex::new(server_apis));
 
         let players = Players::load(format!("{}/{}/players.json", config_path, Self::id(),));
         serenity_data.insert::<Players>(Arc::new(Mutex::new(players)));
@@ -182,9 +202,6 @@ impl discord_config::DiscordGame for DiscordConfig {
             TpLocations::load(format!("{}/{}/tp_locations.json", config_path, Self::id(),));
         serenity_data.insert::<TpLocations>(Arc::new(Mutex::new(tp_locations)));
 
-        tokio::spawn(runner.run(addr.clone()));
-        tokio::spawn(client_handler.run(addr.clone()));
-
         Ok(())
     }
 
@@ -218,9 +235,7 @@ pub struct DiscordConfig {
 impl DiscordConfig {
     pub fn is_chat_channel(&self, channel_id: serenity::model::prelude::ChannelId) -> bool {
         // self.chat_channels.iter().map(|c| c.id).contains(&channel_id.0)
-        self.chat_channels
-            .iter()
-            .any(|c| c.id == channel_id.0)
+        self.chat_channels.iter().any(|c| c.id == channel_id.0)
     }
 
     pub fn toggle_server_messages(&mut self, enabled: bool) {
@@ -234,7 +249,7 @@ impl DiscordConfig {
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct WebhookConfig {
-    id: u64, 
+    id: u64,
     token: String,
 }
 
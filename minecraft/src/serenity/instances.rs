@@ -0,0 +1,63 @@
+use std::sync::Mutex;
+
+use ws_protocol::WsClient;
+
+use crate::server;
+
+#[derive(Default)]
+pub struct ServerApis(Vec<(Option<String>, WsClient<server::Api>)>);
+
+impl ServerApis {
+    pub fn insert(&mut self, instance: Option<String>, api: WsClient<server::Api>) {
+        self.0.push((instance, api));
+    }
+
+    pub fn get(&self, instance: Option<&str>) -> Result<&WsClient<server::Api>, String> {
+        match instance {
+            Some(instance) => self
+                .0
+                .iter()
+                .find(|(name, _)| name.as_deref() == Some(instance))
+                .map(|(_, api)| api)
+                .ok_or_else(|| format!("There's no Minecraft server called `{instance}`.")),
+            None => match self.0.as_slice() {
+                [(_, api)] => Ok(api),
+                _ => Err(format!(
+                    "Which Minecraft server? Start the command with one of: {}.",
+                    self.names()
+                )),
+            },
+        }
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &WsClient<server::Api>> {
+        self.0.iter().map(|(_, api)| api)
+    }
+
+    pub fn split_instance<'a>(&self, args: &'a [&'a str]) -> (Option<&'a str>, &'a [&'a str]) {
+        match args {
+            [first, rest @ ..]
+                if self
+                    .0
+                    .iter()
+                    .any(|(name, _)| name.as_deref() == Some(*first)) =>
+            {
+                (Some(first), rest)
+            }
+            _ => (None, args),
+        }
+    }
+
+    fn names(&self) -> String {
+        self.0
+            .iter()
+            .filter_map(|(name, _)| name.as_deref())
+            .map(|name| format!("`{name}`"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl serenity::prelude::TypeMapKey for ServerApis {
+    type Value = Mutex<ServerApis>;
+}
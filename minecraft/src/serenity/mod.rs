@@ -17,6 +17,9 @@ use crate::{
 mod config;
 pub use self::config::*;
 
+mod instances;
+pub use instances::*;
+
 mod json;
 pub use json::*;
 
@@ -37,23 +40,37 @@ macro_rules! command_args {
 }
 
 #[bjorn_command(DiscordConfig)]
-pub async fn mstart(ctx: &Context, _: &Message) -> CommandResult {
-    dispatch(ctx, server::Message::Start).await
+pub async fn mstart(ctx: &Context, msg: &Message) -> CommandResult {
+    let (instance, _) = instance_args(ctx, msg).await;
+    dispatch(ctx, msg, instance.as_deref(), server::Message::Start).await
 }
 
 #[bjorn_command(DiscordConfig, admin)]
-pub async fn mstop(ctx: &Context, _: &Message) -> CommandResult {
-    dispatch(ctx, server::Message::Stop).await
+pub async fn mstop(ctx: &Context, msg: &Message) -> CommandResult {
+    let (instance, _) = instance_args(ctx, msg).await;
+    dispatch(ctx, msg, instance.as_deref(), server::Message::Stop).await
 }
 
 #[bjorn_command(DiscordConfig)]
-pub async fn save(ctx: &Context, _: &Message) -> CommandResult {
-    dispatch(ctx, server::Message::Save).await
+pub async fn save(ctx: &Context, msg: &Message) -> CommandResult {
+    let (instance, _) = instance_args(ctx, msg).await;
+    dispatch(ctx, msg, instance.as_deref(), server::Message::Save).await
 }
 
 #[bjorn_command(DiscordConfig)]
-pub async fn players(ctx: &Context, _: &Message) -> CommandResult {
-    dispatch(ctx, server::Message::QueryPlayers).await
+pub async fn players(ctx: &Context, msg: &Message) -> CommandResult {
+    let (instance, _) = instance_args(ctx, msg).await;
+    let reply = match request(ctx, instance.as_deref(), server::Message::QueryPlayers).await {
+        Ok(reply) => {
+            let data = ctx.data.read().await;
+            let players = data.get::<Players>().unwrap().lock().unwrap();
+            reply.to_string(&players)
+        }
+        Err(e) => format!("Couldn't get the player list: {e}"),
+    };
+
+    msg.reply(ctx, reply).await?;
+    Ok(())
 }
 
 #[bjorn_command(DiscordConfig)]
@@ -76,13 +93,14 @@ pub async fn tp(ctx: &Context, msg: &Message) -> CommandResult {
         }
     };
 
-    match command_args!(msg.content) {
+    let (instance, args) = instance_args(ctx, msg).await;
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
         [] => send_tp_help_text(ctx, msg).await,
         ["set", "list"] => list_saved_locations(ctx, msg).await,
         ["set", name, realm, x, y, z] => save_tp_location(ctx, msg, name, realm, x, y, z, false).await,
         ["set", name, realm, x, y, z, "force"] => save_tp_location(ctx, msg, name, realm, x, y, z, true).await,
         ["set", ..] => send_tp_set_help_text(ctx, msg).await,
-        [target, ..] => teleport(ctx, msg, name, target).await,
+        [target, ..] => teleport(ctx, msg, instance.as_deref(), name, target).await,
     }
 }
 
@@ -127,32 +145,38 @@ pub async fn messages(ctx: &Context, msg: &Message) -> CommandResult {
 }
 
 #[bjorn_command(DiscordConfig, admin)]
-pub async fn backup(ctx: &Context, _: &Message) -> CommandResult {
-    dispatch(ctx, server::Message::BackupWorld).await
+pub async fn backup(ctx: &Context, msg: &Message) -> CommandResult {
+    let (instance, _) = instance_args(ctx, msg).await;
+    dispatch(ctx, msg, instance.as_deref(), server::Message::BackupWorld).await
 }
 
 #[bjorn_command(DiscordConfig, admin)]
 pub async fn cmd(ctx: &Context, msg: &Message) -> CommandResult {
-    let command_text = match command_args!(msg.content) {
-        [args @ ..] => {
-            let data = ctx.data.read().await;
-            let players = data.get::<Players>().unwrap().lock().unwrap();
-            
-            args.into_iter().map(|arg| {
-                match Mention::from_str(ctx, arg) {
-                    Ok(Mention::User(UserId(user_id))) => players.get_registered_name(user_id).unwrap_or(String::from(*arg)),
-                    _ => String::from(*arg),
-                }
-            }).collect::<Vec<_>>().join(" ")
-        }
+    let (instance, args) = instance_args(ctx, msg).await;
+    let command_text = {
+        let data = ctx.data.read().await;
+        let players = data.get::<Players>().unwrap().lock().unwrap();
+
+        args.into_iter().map(|arg| {
+            match Mention::from_str(ctx, &arg) {
+                Ok(Mention::User(UserId(user_id))) => players.get_registered_name(user_id).unwrap_or(arg),
+                _ => arg,
+            }
+        }).collect::<Vec<_>>().join(" ")
     };
 
-    dispatch(ctx, server::Message::Command(command_text)).await
+    dispatch(ctx, msg, instance.as_deref(), server::Message::Command(command_text)).await
 }
 
-async fn teleport(ctx: &Context, msg: &Message, player: String, target: &str) -> CommandResult {
+async fn teleport(
+    ctx: &Context,
+    msg: &Message,
+    instance: Option<&str>,
+    player: String,
+    target: &str,
+) -> CommandResult {
     if &target[0..1] == "$" {
-        return tp_saved_location(ctx, msg, player, &target[1..]).await;
+        return tp_saved_location(ctx, msg, instance, player, &target[1..]).await;
     }
 
     let target = match Mention::from_str(ctx, target) {
@@ -178,13 +202,14 @@ async fn teleport(ctx: &Context, msg: &Message, player: String, target: &str) ->
         msg.reply(ctx, "You can't teleport to yourself.").await?;
         Ok(())
     } else {
-        dispatch(ctx, server::Message::Tp(player, target)).await
+        dispatch(ctx, msg, instance, server::Message::Tp(player, target)).await
     }
 }
 
 async fn tp_saved_location(
     ctx: &Context,
     msg: &Message,
+    instance: Option<&str>,
     player: String,
     target: &str,
 ) -> CommandResult {
@@ -206,7 +231,7 @@ async fn tp_saved_location(
         }
     };
 
-    dispatch(ctx, server::Message::TpLoc(player, coords)).await
+    dispatch(ctx, msg, instance, server::Message::TpLoc(player, coords)).await
 }
 
 async fn list_saved_locations(ctx: &Context, msg: &Message) -> CommandResult {
@@ -346,16 +371,80 @@ async fn register_player_name(ctx: &Context, msg: &Message, name: &str) -> Comma
     Ok(())
 }
 
-async fn dispatch(ctx: &Context, message: server::Message) -> CommandResult {
+async fn instance_args(ctx: &Context, msg: &Message) -> (Option<String>, Vec<String>) {
+    let args = msg.content.split_whitespace().skip(1).collect::<Vec<_>>();
+
     let data = ctx.data.read().await;
+    let apis = data.get::<ServerApis>().unwrap().lock().unwrap();
+    let (instance, args) = apis.split_instance(&args);
 
-    let api = data
-        .get::<ws_protocol::WsClient<server::Api>>()
-        .unwrap()
-        .lock()
-        .unwrap();
+    (
+        instance.map(String::from),
+        args.iter().map(|arg| String::from(*arg)).collect(),
+    )
+}
 
-    api.send(message);
+async fn dispatch(
+    ctx: &Context,
+    msg: &Message,
+    instance: Option<&str>,
+    message: server::Message,
+) -> CommandResult {
+    let dispatched = {
+        let data = ctx.data.read().await;
+        let apis = data.get::<ServerApis>().unwrap().lock().unwrap();
+
+        apis.get(instance)
+            .map(|api| (api.send_confirmed(message), *api.connection_state().borrow()))
+    };
+
+    let (delivery, link_state) = match dispatched {
+        Ok(dispatched) => dispatched,
+        Err(e) => {
+            msg.reply(ctx, e).await?;
+            return Ok(());
+        }
+    };
+
+    match delivery.await {
+        Ok(ws_protocol::DeliveryStatus::Delivered(_)) => {}
+        Ok(ws_protocol::DeliveryStatus::Stored) => {
+            msg.reply(ctx, "The Minecraft game manager isn't connected. Your command will be delivered when it reconnects.").await?;
+        }
+        Ok(ws_protocol::DeliveryStatus::NoRoute) => {
+            msg.reply(ctx, "The Minecraft game manager isn't connected. Try again later.").await?;
+        }
+        Ok(ws_protocol::DeliveryStatus::Forbidden) => {
+            msg.reply(ctx, "Bjorn isn't allowed to send Minecraft commands.").await?;
+        }
+        Ok(ws_protocol::DeliveryStatus::RateLimited) => {
+            msg.reply(ctx, "Bjorn is sending Minecraft commands too quickly. Try again later.").await?;
+        }
+        Ok(ws_protocol::DeliveryStatus::TooLarge) => {
+            msg.reply(ctx, "Your Minecraft command is too large to send.").await?;
+        }
+        Err(ws_protocol::RequestError::Timeout) => {
+            msg.reply(ctx, "Couldn't confirm that the Minecraft game manager received your command.").await?;
+        }
+        Err(_) => {
+            msg.reply(ctx, format!("The Minecraft game manager is unreachable (WS link {link_state}). Try again later.")).await?;
+        }
+    }
 
     Ok(())
 }
+
+async fn request(
+    ctx: &Context,
+    instance: Option<&str>,
+    message: server::Message,
+) -> Result<client::Message, String> {
+    let reply = {
+        let data = ctx.data.read().await;
+        let apis = data.get::<ServerApis>().unwrap().lock().unwrap();
+
+        apis.get(instance).map(|api| api.request(message))
+    };
+
+    reply?.await.map_err(|e| e.to_string())
+}
@@ -9,7 +9,7 @@ use process::*;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 
-use crate::client;
+use crate::{client, instance};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RealmCoords {
@@ -59,6 +59,7 @@ pub struct Api;
 
 impl ws_protocol::ClientApi for Api {
     type Message = Message;
+    type Reply = client::Message;
 
     fn id() -> &'static str {
         "minecraft_server"
@@ -66,23 +67,24 @@ impl ws_protocol::ClientApi for Api {
 }
 
 pub struct Handler {
+    instance: Option<String>,
     client_api: Arc<Mutex<ws_protocol::WsClient<client::Api>>>,
     server_process: MinecraftServerProcess,
     players: Arc<Mutex<Vec<String>>>,
 }
 
 impl Handler {
-    pub fn new(client_api: ws_protocol::WsClient<client::Api>) -> Handler {
-        let server_dir = std::env::var("BJORN_MINECRAFT_SERVER")
+    pub fn new(client_api: ws_protocol::WsClient<client::Api>, instance: Option<String>) -> Handler {
+        let server_dir = instance::instance_var("BJORN_MINECRAFT_SERVER", instance.as_deref())
             .expect("Minecraft server environment not properly configured.");
 
-        let server_jar = std::env::var("BJORN_MINECRAFT_SERVER_JAR")
+        let server_jar = instance::var("BJORN_MINECRAFT_SERVER_JAR", instance.as_deref())
             .unwrap_or("server.jar".into());
 
-        let max_memory = std::env::var("BJORN_MINECRAFT_MAX_MEMORY")
+        let max_memory = instance::var("BJORN_MINECRAFT_MAX_MEMORY", instance.as_deref())
             .unwrap_or("4G".into());
 
-        let backup_path = std::env::var("BJORN_MINECRAFT_BACKUP_PATH").ok();
+        let backup_path = instance::var("BJORN_MINECRAFT_BACKUP_PATH", instance.as_deref()).ok();
 
         let mut server_process = MinecraftServerProcess::build(&server_dir, &server_jar, &max_memory, backup_path);
         let players = Arc::new(Mutex::new(vec![]));
@@ -92,17 +94,22 @@ impl Handler {
         {
             let client_api = client_api.clone();
             let players = players.clone();
+            let log_prefix = match &instance {
+                Some(instance) => format!("[Minecraft {instance}]"),
+                None => String::from("[Minecraft]"),
+            };
 
             server_process.handle_stdout(move |line| {
                 if let Some(message) = parse_line(line, &players) {
                     client_api.lock().unwrap().send(message);
                 }
 
-                println!("[Minecraft] {line}");
+                println!("{log_prefix} {line}");
             });
         }
 
         Handler {
+            instance,
             client_api,
             server_process,
             players,
@@ -110,14 +117,29 @@ impl Handler {
     }
 
     pub fn is_configured() -> bool {
-        std::env::var("BJORN_MINECRAFT_SERVER").is_ok()
+        !Self::configured_instances().is_empty()
+    }
+
+    pub fn configured_instances() -> Vec<Option<String>> {
+        instance::instances()
+            .into_iter()
+            .filter(|instance| {
+                instance::instance_var("BJORN_MINECRAFT_SERVER", instance.as_deref()).is_ok()
+            })
+            .collect()
     }
 }
 
+#[serenity::async_trait]
 impl ws_protocol::ClientApiHandler for Handler {
     type Api = Api;
+    type Error = MinecraftServerProcessError;
 
-    fn handle_message(&mut self, message: Message) {
+    fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+
+    async fn handle_message(&mut self, message: Message) -> Result<(), Self::Error> {
         let client_api = self.client_api.lock().unwrap();
         match message {
             Message::Start => self
@@ -168,6 +190,24 @@ impl ws_protocol::ClientApiHandler for Handler {
                 self.server_process.command(&format!("{text}\n"))
             }
         }
-        .unwrap_or_else(|e| client_api.send(client::Message::Info(e.to_string())));
+    }
+
+    async fn handle_request(
+        &mut self,
+        message: Message,
+    ) -> Result<Option<client::Message>, Self::Error> {
+        match message {
+            Message::QueryPlayers => Ok(Some(client::Message::Players(
+                self.players.lock().unwrap().clone(),
+            ))),
+            message => self.handle_message(message).await.map(|_| None),
+        }
+    }
+
+    fn on_error(&mut self, error: Self::Error) {
+        self.client_api
+            .lock()
+            .unwrap()
+            .send(client::Message::Info(error.to_string()));
     }
 }
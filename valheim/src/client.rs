@@ -32,6 +32,7 @@ pub struct Api;
 
 impl ws_protocol::ClientApi for Api {
     type Message = Message;
+    type Reply = ();
 
     fn id() -> &'static str {
         "valheim_client"
@@ -55,14 +56,21 @@ impl Handler {
     }
 }
 
+#[serenity::async_trait]
 impl ws_protocol::ClientApiHandler for Handler {
     type Api = Api;
+    type Error = serenity::Error;
 
-    fn handle_message(&mut self, message: <Self::Api as ws_protocol::ClientApi>::Message) {
-        tokio::spawn(MessageHandler::server_message(
+    async fn handle_message(
+        &mut self,
+        message: <Self::Api as ws_protocol::ClientApi>::Message,
+    ) -> Result<(), Self::Error> {
+        MessageHandler::server_message(
             self.data.clone(),
             self.cache_and_http.clone(),
+            None,
             message,
-        ));
+        )
+        .await
     }
 }
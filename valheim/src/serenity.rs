@@ -31,17 +31,17 @@ macro_rules! command_args {
 
 #[bjorn_command(DiscordConfig)]
 pub async fn vstart(ctx: &Context, msg: &Message) -> CommandResult {
-    let msg = match command_args!(msg.content) {
+    let message = match command_args!(msg.content) {
         ["crossplay"] => server::Message::Start(true),
         [..] => server::Message::Start(false),
     };
 
-    dispatch(ctx, msg).await
+    dispatch(ctx, msg, message).await
 }
 
 #[bjorn_command(DiscordConfig, admin)]
-pub async fn vstop(ctx: &Context, _: &Message) -> CommandResult {
-    dispatch(ctx, server::Message::Stop).await
+pub async fn vstop(ctx: &Context, msg: &Message) -> CommandResult {
+    dispatch(ctx, msg, server::Message::Stop).await
 }
 
 #[bjorn_command(DiscordConfig)]
@@ -53,8 +53,28 @@ pub async fn vplayer(ctx: &Context, msg: &Message) -> CommandResult {
 }
 
 #[bjorn_command(DiscordConfig)]
-pub async fn haldor(ctx: &Context, _msg: &Message) -> CommandResult {
-    dispatch(ctx, server::Message::QueryHaldor).await
+pub async fn haldor(ctx: &Context, msg: &Message) -> CommandResult {
+    let reply = match request(ctx, server::Message::QueryHaldor).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            msg.reply(ctx, format!("Couldn't find Haldor: {e}")).await?;
+            return Ok(());
+        }
+    };
+
+    let (players, attack_messages) = {
+        let data = ctx.data.read().await;
+        let players = data.get::<Players>().unwrap().lock().unwrap();
+        let attack_messages = data.get::<AttackMessages>().unwrap().lock().unwrap();
+
+        (players.clone(), attack_messages.clone())
+    };
+
+    reply
+        .send_discord_message(&players, &attack_messages, &ctx.http, &msg.channel_id)
+        .await?;
+
+    Ok(())
 }
 
 async fn echo_player_name(ctx: &Context, msg: &Message) -> CommandResult {
@@ -89,20 +109,66 @@ async fn register_player_name(ctx: &Context, msg: &Message, name: &str) -> Comma
     Ok(())
 }
 
-async fn dispatch(ctx: &Context, message: server::Message) -> CommandResult {
-    let data = ctx.data.read().await;
+async fn dispatch(ctx: &Context, msg: &Message, message: server::Message) -> CommandResult {
+    let (delivery, link_state) = {
+        let data = ctx.data.read().await;
 
-    let api = data
-        .get::<ws_protocol::WsClient<server::Api>>()
-        .unwrap()
-        .lock()
-        .unwrap();
+        let api = data
+            .get::<ws_protocol::WsClient<server::Api>>()
+            .unwrap()
+            .lock()
+            .unwrap();
 
-    api.send(message);
+        (api.send_confirmed(message), *api.connection_state().borrow())
+    };
+
+    match delivery.await {
+        Ok(ws_protocol::DeliveryStatus::Delivered(_)) => {}
+        Ok(ws_protocol::DeliveryStatus::Stored) => {
+            msg.reply(ctx, "The Valheim game manager isn't connected. Your command will be delivered when it reconnects.").await?;
+        }
+        Ok(ws_protocol::DeliveryStatus::NoRoute) => {
+            msg.reply(ctx, "The Valheim game manager isn't connected. Try again later.").await?;
+        }
+        Ok(ws_protocol::DeliveryStatus::Forbidden) => {
+            msg.reply(ctx, "Bjorn isn't allowed to send Valheim commands.").await?;
+        }
+        Ok(ws_protocol::DeliveryStatus::RateLimited) => {
+            msg.reply(ctx, "Bjorn is sending Valheim commands too quickly. Try again later.").await?;
+        }
+        Ok(ws_protocol::DeliveryStatus::TooLarge) => {
+            msg.reply(ctx, "Your Valheim command is too large to send.").await?;
+        }
+        Err(ws_protocol::RequestError::Timeout) => {
+            msg.reply(ctx, "Couldn't confirm that the Valheim game manager received your command.").await?;
+        }
+        Err(_) => {
+            msg.reply(ctx, format!("The Valheim game manager is unreachable (WS link {link_state}). Try again later.")).await?;
+        }
+    }
 
     Ok(())
 }
 
+async fn request(
+    ctx: &Context,
+    message: server::Message,
+) -> Result<client::Message, ws_protocol::RequestError> {
+    let reply = {
+        let data = ctx.data.read().await;
+
+        let api = data
+            .get::<ws_protocol::WsClient<server::Api>>()
+            .unwrap()
+            .lock()
+            .unwrap();
+
+        api.request(message)
+    };
+
+    reply.await
+}
+
 macro_rules! with_mention {
     ($players:expr, $player:expr) => {{
         $players
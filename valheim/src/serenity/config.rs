@@ -5,7 +5,6 @@ use serenity::{
     async_trait, framework::standard::macros::group, http::Typing, model::prelude::Message,
     prelude::*,
 };
-use ws_protocol::WsTask;
 
 use super::*;
 
@@ -26,8 +25,9 @@ impl discord_config::BjornMessageHandler for MessageHandler {
     async fn server_message(
         data: Arc<tokio::sync::RwLock<TypeMap>>,
         http_and_cache: Arc<serenity::CacheAndHttp>,
+        _instance: Option<String>,
         message: client::Message,
-    ) {
+    ) -> Result<(), serenity::Error> {
         let (players, attack_messages) = {
             let data = data.read().await;
             let players = data.get::<Players>().unwrap().lock().unwrap();
@@ -37,10 +37,11 @@ impl discord_config::BjornMessageHandler for MessageHandler {
         };
 
         use_data!(data, |config: DiscordConfig| {
+            let mut result = Ok(());
             let mut typing_results = vec![];
             for channel in &config.listen_channels {
                 let channel = http_and_cache.cache.channel(*channel).unwrap().id();
-                message
+                if let Err(e) = message
                     .send_discord_message(
                         &players,
                         &attack_messages,
@@ -48,7 +49,9 @@ impl discord_config::BjornMessageHandler for MessageHandler {
                         &channel,
                     )
                     .await
-                    .unwrap();
+                {
+                    result = Err(e);
+                }
 
                 if message.indicates_follow_up() {
                     if let Ok(typing) = channel.start_typing(&http_and_cache.http) {
@@ -73,7 +76,9 @@ impl discord_config::BjornMessageHandler for MessageHandler {
                     }
                 }
             }
-        });
+
+            result
+        })
     }
 }
 
@@ -90,13 +95,12 @@ impl discord_config::DiscordGame for DiscordConfig {
     fn setup(
         setup_data: discord_config::DiscordGameSetupData,
         serenity_data: &mut serenity::prelude::TypeMap,
-        canceller: &mut discord_config::Canceller,
+        connection: &mut ws_protocol::WsConnection,
     ) -> Result<(), ()> {
         let discord_config::DiscordGameSetupData {
             config_path,
             data,
             cache_and_http,
-            addr,
         } = setup_data;
 
         let config = match discord_config::load_config(&config_path) {
@@ -106,12 +110,9 @@ impl discord_config::DiscordGame for DiscordConfig {
 
         serenity_data.insert::<DiscordConfig>(Mutex::new(Some(config)));
 
-        let (server_api, runner, out_canceller) = ws_protocol::WsClient::<server::Api>::new();
-        let (client_handler, in_canceller) =
-            ws_protocol::WsClientHandler::new(client::Handler::new(data, cache_and_http));
-
-        canceller.add(out_canceller);
-        canceller.add(in_canceller);
+        let server_api =
+            connection.client_with_policy::<server::Api>(ws_protocol::OutboxPolicy::Reject);
+        connection.handler(client::Handler::new(data, cache_and_http));
 
         serenity_data.insert::<ws_protocol::WsClient<server::Api>>(Mutex::new(server_api));
 
@@ -125,9 +126,6 @@ impl discord_config::DiscordGame for DiscordConfig {
         ));
         serenity_data.insert::<AttackMessages>(Arc::new(Mutex::new(attack_messages)));
 
-        tokio::spawn(runner.run(addr.clone()));
-        tokio::spawn(client_handler.run(addr.clone()));
-
         Ok(())
     }
 
@@ -24,6 +24,7 @@ pub struct Api;
 
 impl ws_protocol::ClientApi for Api {
     type Message = Message;
+    type Reply = client::Message;
 
     fn id() -> &'static str {
         "valheim_server"
@@ -110,10 +111,15 @@ impl Handler {
     }
 }
 
+#[serenity::async_trait]
 impl ws_protocol::ClientApiHandler for Handler {
     type Api = Api;
+    type Error = ValheimServerProcessError;
 
-    fn handle_message(&mut self, message: <Self::Api as ws_protocol::ClientApi>::Message) {
+    async fn handle_message(
+        &mut self,
+        message: <Self::Api as ws_protocol::ClientApi>::Message,
+    ) -> Result<(), Self::Error> {
         let client_api = self.client_api.lock().unwrap();
         match message {
             Message::Start(crossplay) => self
@@ -133,6 +139,24 @@ impl ws_protocol::ClientApiHandler for Handler {
                 get_haldor_locations(&self.world_path),
             ))),
         }
-        .unwrap_or_else(|e| client_api.send(client::Message::Info(e.to_string())));
+    }
+
+    async fn handle_request(
+        &mut self,
+        message: <Self::Api as ws_protocol::ClientApi>::Message,
+    ) -> Result<Option<client::Message>, Self::Error> {
+        match message {
+            Message::QueryHaldor => Ok(Some(client::Message::Haldor(get_haldor_locations(
+                &self.world_path,
+            )))),
+            message => self.handle_message(message).await.map(|_| None),
+        }
+    }
+
+    fn on_error(&mut self, error: Self::Error) {
+        self.client_api
+            .lock()
+            .unwrap()
+            .send(client::Message::Info(error.to_string()));
     }
 }
@@ -1,15 +1,59 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{console, MessageEvent, WebSocket};
+use ws_protocol_version::{ObserverIdentification, ServerIdentification, PROTOCOL_VERSION};
 use yew::prelude::*;
 
 macro_rules! console_log {
     ($($t:tt)*) => (console::log_1(&format_args!($($t)*).to_string().as_str().into()))
 }
 
+const SUBSCRIPTIONS: &[&str] = &["minecraft_client", "valheim_client"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Handshake {
+    AwaitingServer,
+    AwaitingAcceptance,
+    Complete,
+}
+
+// The observer's side of ws_protocol's Handshake, which doesn't build for wasm.
+#[derive(Deserialize)]
+enum ServerHandshake {
+    ServerIdentification(ServerIdentification),
+}
+
+#[derive(Serialize)]
+enum ObserverHandshake {
+    Web(ObserverIdentification),
+}
+
+fn observer_identification() -> String {
+    let identification = ObserverIdentification {
+        subscriptions: SUBSCRIPTIONS
+            .iter()
+            .map(|target| target.to_string())
+            .collect(),
+        // Anything built into the dashboard is public, so it can't hold a secret. The server has
+        // to let anonymous observers in (BJORN_WS_ANONYMOUS_OBSERVERS).
+        proof: None,
+        protocol_version: PROTOCOL_VERSION,
+    };
+
+    serde_json::to_string(&ObserverHandshake::Web(identification)).unwrap()
+}
+
+fn is_server_identification(message: &js_sys::JsString) -> bool {
+    matches!(
+        serde_json::from_str(&String::from(message)),
+        Ok(ServerHandshake::ServerIdentification(_))
+    )
+}
+
 #[derive(Clone, PartialEq)]
 pub enum WsConnection {
     Disconnected,
@@ -24,22 +68,34 @@ impl WsConnection {
         };
 
         ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
-        let handshake_successful = Rc::new(RefCell::new(false));
+        let handshake = Rc::new(RefCell::new(Handshake::AwaitingServer));
         let ws_ = ws.clone();
         let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
             if let Ok(message) = e.data().dyn_into::<js_sys::JsString>() {
-                if *handshake_successful.borrow() {
-                    console_log!("Received {message}");
-                } else if message.loose_eq(&"Bjorn".into()) {
-                    console_log!("Hanshake with Bjorn WS successful");
-                    *handshake_successful.borrow_mut() = true;
-                    ws_.send_with_str("web").unwrap();
-                } else {
-                    console_log!(
-                        "Connection doesn't appear to be bjorn (received {message}). Aborting."
-                    );
-                    ws_.close_with_code_and_reason(1000, "Unknown server")
-                        .unwrap();
+                let state = *handshake.borrow();
+                match state {
+                    Handshake::Complete => console_log!("Received {message}"),
+                    Handshake::AwaitingServer if is_server_identification(&message) => {
+                        console_log!("Bjorn server identified");
+                        *handshake.borrow_mut() = Handshake::AwaitingAcceptance;
+                        ws_.send_with_str(&observer_identification()).unwrap();
+                    }
+                    Handshake::AwaitingServer => {
+                        console_log!(
+                            "Connection doesn't appear to be bjorn (received {message}). Aborting."
+                        );
+                        ws_.close_with_code_and_reason(1000, "Unknown server")
+                            .unwrap();
+                    }
+                    Handshake::AwaitingAcceptance if message.loose_eq(&"\"Accepted\"".into()) => {
+                        console_log!("Hanshake with Bjorn WS successful");
+                        *handshake.borrow_mut() = Handshake::Complete;
+                    }
+                    Handshake::AwaitingAcceptance => {
+                        console_log!("Bjorn WS rejected the handshake: {message}");
+                        ws_.close_with_code_and_reason(1000, "Handshake rejected")
+                            .unwrap();
+                    }
                 }
             } else {
                 console::log_2(&"Unknown message received:".into(), &e.data());
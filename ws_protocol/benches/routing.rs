@@ -0,0 +1,175 @@
+// Routing throughput over the in-process transport, so the numbers are about the server
+// rather than the network:
+//
+//     cargo bench -p ws_protocol --features client,server --bench routing -- [connections] [messages]
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use ws_protocol::{ClientApiHandler, Loopback, QueueLimits, ServerConfig, WsClient, WsConnection};
+
+use common::{client_config, spawn_connection, start_server, wait_until, Echo, EchoMessage};
+
+struct Counter {
+    instance: Option<String>,
+    received: mpsc::UnboundedSender<()>,
+}
+
+#[async_trait]
+impl ClientApiHandler for Counter {
+    type Api = Echo;
+    type Error = String;
+
+    async fn handle_message(&mut self, _message: EchoMessage) -> Result<(), String> {
+        self.received.send(()).map_err(|e| e.to_string())
+    }
+
+    fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+}
+
+struct Bench {
+    loopback: Loopback,
+    received: mpsc::UnboundedSender<()>,
+    _running: Vec<Box<dyn std::any::Any>>,
+}
+
+impl Bench {
+    fn start() -> (Bench, mpsc::UnboundedReceiver<()>) {
+        let loopback = Loopback::new();
+
+        // Every message has to arrive for the numbers to mean anything.
+        let server = start_server(
+            &loopback,
+            ServerConfig {
+                queue: QueueLimits {
+                    capacity: usize::MAX,
+                    ..QueueLimits::default()
+                },
+                ..ServerConfig::default()
+            },
+        );
+
+        let (received, received_rx) = mpsc::unbounded_channel();
+        let bench = Bench {
+            loopback,
+            received,
+            _running: vec![Box::new(server)],
+        };
+        (bench, received_rx)
+    }
+
+    fn handler(&mut self, instance: Option<String>) {
+        let mut connection = WsConnection::with_config(client_config());
+        connection.handler(Counter {
+            instance,
+            received: self.received.clone(),
+        });
+        self.spawn(connection);
+    }
+
+    fn emitter(&mut self, instance: Option<&str>) -> WsClient<Echo> {
+        // Messages are sent all at once, faster than they can be written.
+        let mut config = client_config();
+        config.outbox_capacity = 1 << 20;
+        let mut connection = WsConnection::with_config(config);
+        let echo = connection.instance_client::<Echo>(instance).unwrap();
+        self.spawn(connection);
+        echo
+    }
+
+    fn spawn(&mut self, connection: WsConnection) {
+        let canceller = spawn_connection(&self.loopback, connection);
+        self._running.push(Box::new(canceller));
+    }
+}
+
+async fn receive(received: &mut mpsc::UnboundedReceiver<()>, count: usize) -> Duration {
+    let started = Instant::now();
+    for _ in 0..count {
+        received.recv().await.unwrap();
+    }
+    started.elapsed()
+}
+
+fn report(name: &str, deliveries: usize, elapsed: Duration) {
+    println!(
+        "{name}: {deliveries} deliveries in {:.2?} ({:.0}/s)",
+        elapsed,
+        deliveries as f64 / elapsed.as_secs_f64()
+    );
+}
+
+// One emitter, every handler gets every message.
+async fn fan_out(connections: usize, messages: usize) {
+    let (mut bench, mut received) = Bench::start();
+    for _ in 0..connections {
+        bench.handler(None);
+    }
+    let echo = bench.emitter(None);
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == connections).await;
+
+    let started = Instant::now();
+    for i in 0..messages {
+        echo.send(EchoMessage::Say(i.to_string()));
+    }
+    receive(&mut received, connections * messages).await;
+
+    report(
+        &format!("fan-out to {connections} handlers"),
+        connections * messages,
+        started.elapsed(),
+    );
+}
+
+// Emitter and handler pairs on their own instances, all sending at once.
+async fn pairs(connections: usize, messages: usize) {
+    let (mut bench, mut received) = Bench::start();
+    let pairs = connections / 2;
+
+    let mut emitters = vec![];
+    for pair in 0..pairs {
+        let instance = format!("pair-{pair}");
+        bench.handler(Some(instance.clone()));
+        emitters.push(bench.emitter(Some(&instance)));
+    }
+    for echo in &emitters {
+        wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+    }
+
+    let started = Instant::now();
+    for echo in emitters {
+        tokio::spawn(async move {
+            for i in 0..messages {
+                echo.send(EchoMessage::Say(i.to_string()));
+            }
+        });
+    }
+    receive(&mut received, pairs * messages).await;
+
+    report(
+        &format!("{pairs} emitter/handler pairs"),
+        pairs * messages,
+        started.elapsed(),
+    );
+}
+
+fn main() {
+    let mut args = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse::<usize>().ok());
+    let connections = args.next().unwrap_or(200);
+    let messages = args.next().unwrap_or(1000);
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(fan_out(connections, messages));
+    runtime.block_on(pairs(connections, messages * 10));
+}
@@ -0,0 +1,18 @@
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+use ws_protocol_version::prove;
+
+pub fn generate_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Checked against the same proof the client computes, in constant time so the comparison
+// doesn't leak how much of a guess was right.
+pub fn verify(secret: &str, challenge: &str, proof: &str) -> bool {
+    prove(secret, challenge)
+        .as_bytes()
+        .ct_eq(proof.as_bytes())
+        .into()
+}
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use crate::{Codec, Heartbeat};
+
+use super::{OutboxPolicy, ReconnectPolicy};
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub identity: Option<String>,
+    pub secret: Option<String>,
+    pub tls: ClientTlsConfig,
+    pub request_timeout: Duration,
+    pub outbox_policy: OutboxPolicy,
+    pub reconnect: ReconnectPolicy,
+    pub heartbeat: Heartbeat,
+    pub shutdown_timeout: Duration,
+    pub codec: Codec,
+    // Messages received for each handler or client that it hasn't gotten to yet.
+    pub inbox_capacity: usize,
+    // What happens to a message for a handler or client whose inbox is full.
+    pub inbox_policy: InboxPolicy,
+    // Messages sent on the connection that haven't been written yet, including any waiting
+    // for it to reconnect.
+    pub outbox_capacity: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            identity: None,
+            secret: None,
+            tls: ClientTlsConfig::default(),
+            request_timeout: Duration::from_secs(10),
+            outbox_policy: OutboxPolicy::Queue {
+                ttl: Duration::from_secs(60),
+            },
+            reconnect: ReconnectPolicy::default(),
+            heartbeat: Heartbeat::default(),
+            shutdown_timeout: Duration::from_secs(5),
+            codec: Codec::Json,
+            inbox_capacity: 1024,
+            inbox_policy: InboxPolicy::default(),
+            outbox_capacity: 1024,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn from_env() -> ClientConfig {
+        let default = ClientConfig::default();
+
+        ClientConfig {
+            identity: std::env::var("BJORN_WS_IDENTITY").ok(),
+            secret: std::env::var("BJORN_WS_SECRET").ok(),
+            tls: ClientTlsConfig::from_env(),
+            request_timeout: env_secs("BJORN_WS_REQUEST_TIMEOUT")
+                .unwrap_or(default.request_timeout),
+            outbox_policy: std::env::var("BJORN_WS_OUTBOX_POLICY")
+                .ok()
+                .and_then(|policy| OutboxPolicy::parse(&policy))
+                .unwrap_or(default.outbox_policy),
+            reconnect: ReconnectPolicy {
+                initial_delay: env_secs("BJORN_WS_RECONNECT_INITIAL_DELAY")
+                    .unwrap_or(default.reconnect.initial_delay),
+                max_delay: env_secs("BJORN_WS_RECONNECT_MAX_DELAY")
+                    .unwrap_or(default.reconnect.max_delay),
+                max_attempts: std::env::var("BJORN_WS_RECONNECT_MAX_ATTEMPTS")
+                    .ok()
+                    .and_then(|attempts| attempts.parse().ok()),
+                restart_attempts: std::env::var("BJORN_WS_RECONNECT_RESTART_ATTEMPTS")
+                    .ok()
+                    .and_then(|attempts| attempts.parse().ok())
+                    .unwrap_or(default.reconnect.restart_attempts),
+                ..default.reconnect
+            },
+            heartbeat: Heartbeat::from_env(),
+            shutdown_timeout: env_secs("BJORN_WS_SHUTDOWN_TIMEOUT")
+                .unwrap_or(default.shutdown_timeout),
+            codec: std::env::var("BJORN_WS_CODEC")
+                .ok()
+                .and_then(|codec| Codec::parse(&codec))
+                .unwrap_or(default.codec),
+            inbox_capacity: std::env::var("BJORN_WS_INBOX_CAPACITY")
+                .ok()
+                .and_then(|capacity| capacity.parse().ok())
+                .filter(|capacity| *capacity > 0)
+                .unwrap_or(default.inbox_capacity),
+            inbox_policy: std::env::var("BJORN_WS_INBOX_POLICY")
+                .ok()
+                .and_then(|policy| InboxPolicy::parse(&policy))
+                .unwrap_or(default.inbox_policy),
+            outbox_capacity: std::env::var("BJORN_WS_OUTBOX_CAPACITY")
+                .ok()
+                .and_then(|capacity| capacity.parse().ok())
+                .filter(|capacity| *capacity > 0)
+                .unwrap_or(default.outbox_capacity),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InboxPolicy {
+    #[default]
+    DropNewest,
+    Disconnect,
+}
+
+impl InboxPolicy {
+    pub fn parse(value: &str) -> Option<InboxPolicy> {
+        match value {
+            "drop-newest" => Some(InboxPolicy::DropNewest),
+            "disconnect" => Some(InboxPolicy::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+fn env_secs(key: &str) -> Option<Duration> {
+    std::env::var(key)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientTlsConfig {
+    pub ca_path: Option<String>,
+    pub pinned_cert_sha256: Option<String>,
+}
+
+impl ClientTlsConfig {
+    pub fn from_env() -> ClientTlsConfig {
+        ClientTlsConfig {
+            ca_path: std::env::var("BJORN_WS_TLS_CA").ok(),
+            pinned_cert_sha256: std::env::var("BJORN_WS_TLS_PINNED_CERT").ok(),
+        }
+    }
+}
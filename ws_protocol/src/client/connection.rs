@@ -0,0 +1,237 @@
+use std::{marker::PhantomData, sync::Arc, time::Duration};
+
+use futures_util::{future, FutureExt};
+use tokio::sync::{
+    mpsc::{Receiver, Sender},
+    oneshot, watch, Mutex,
+};
+
+use crate::{message::Message, ApiSchema, ApiSpecifier, InvalidInstance};
+
+use super::{
+    route_replies, trace, Canceller, ClientApi, ClientApiHandler, ClientConfig, ConnectionState,
+    Outbox, OutboxPolicy, Outgoing, PendingRequests, Runner, WsClient,
+};
+
+type Inbox = Arc<Mutex<Receiver<Message>>>;
+
+pub struct WsConnection {
+    runner: Runner,
+    cancel: oneshot::Sender<()>,
+    outbox_policy: OutboxPolicy,
+    request_timeout: Duration,
+}
+
+impl Default for WsConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WsConnection {
+    pub fn new() -> WsConnection {
+        Self::with_config(ClientConfig::from_env())
+    }
+
+    pub fn with_config(config: ClientConfig) -> WsConnection {
+        let (cancel, on_cancel) = oneshot::channel();
+
+        WsConnection {
+            outbox_policy: config.outbox_policy,
+            request_timeout: config.request_timeout,
+            runner: Runner::new(config, on_cancel),
+            cancel,
+        }
+    }
+
+    pub fn client<Api>(&mut self) -> WsClient<Api>
+    where
+        Api: ClientApi,
+    {
+        self.client_with_policy(self.outbox_policy)
+    }
+
+    pub fn client_with_policy<Api>(&mut self, outbox_policy: OutboxPolicy) -> WsClient<Api>
+    where
+        Api: ClientApi,
+    {
+        self.route_client(Api::id().into(), outbox_policy)
+    }
+
+    pub fn instance_client<Api>(
+        &mut self,
+        instance: Option<&str>,
+    ) -> Result<WsClient<Api>, InvalidInstance>
+    where
+        Api: ClientApi,
+    {
+        self.instance_client_with_policy(instance, self.outbox_policy)
+    }
+
+    pub fn instance_client_with_policy<Api>(
+        &mut self,
+        instance: Option<&str>,
+        outbox_policy: OutboxPolicy,
+    ) -> Result<WsClient<Api>, InvalidInstance>
+    where
+        Api: ClientApi,
+    {
+        let target = crate::target(Api::id(), instance)?;
+        Ok(self.route_client(target, outbox_policy))
+    }
+
+    fn route_client<Api>(&mut self, target: String, outbox_policy: OutboxPolicy) -> WsClient<Api>
+    where
+        Api: ClientApi,
+    {
+        let (endpoint, peer_schemas) =
+            self.runner
+                .route(ApiSpecifier::Emits(target.clone()), Api::schema(), false);
+        let (message_sink, replies) = endpoint.split();
+        let pending = PendingRequests::default();
+        self.runner
+            .add_task(route_replies(replies, pending.clone()));
+
+        WsClient {
+            _api: PhantomData,
+            target,
+            outbox: Outbox::new(outbox_policy, self.runner.connection_state(), message_sink),
+            pending,
+            deliveries: self.runner.deliveries(),
+            request_timeout: self.request_timeout,
+            peer_schemas,
+        }
+    }
+
+    pub fn handler<Handler>(&mut self, handler: Handler)
+    where
+        Handler: ClientApiHandler + 'static,
+    {
+        self.route_handler(handler);
+    }
+
+    pub fn concurrent_handler<Handler>(&mut self, handler: Handler, concurrency: usize)
+    where
+        Handler: ClientApiHandler + Clone + 'static,
+    {
+        let Some((reply_sink, messages, _)) = self.route_messages(&handler) else {
+            return;
+        };
+        let workers = (0..concurrency.max(1))
+            .map(|_| handle_messages(handler.clone(), reply_sink.clone(), messages.clone()));
+
+        self.runner.add_task(future::join_all(workers).map(|_| ()));
+    }
+
+    pub(crate) fn route_handler<Handler>(
+        &mut self,
+        handler: Handler,
+    ) -> watch::Receiver<Vec<ApiSchema>>
+    where
+        Handler: ClientApiHandler + 'static,
+    {
+        match self.route_messages(&handler) {
+            Some((reply_sink, messages, peer_schemas)) => {
+                self.runner
+                    .add_task(handle_messages(handler, reply_sink, messages));
+                peer_schemas
+            }
+            None => watch::channel(Vec::new()).1,
+        }
+    }
+
+    fn route_messages<Handler>(
+        &mut self,
+        handler: &Handler,
+    ) -> Option<(Sender<Outgoing>, Inbox, watch::Receiver<Vec<ApiSchema>>)>
+    where
+        Handler: ClientApiHandler,
+    {
+        // The rest of the connection is still useful, so a handler with a bad instance name is
+        // left out rather than taking everything else down with it.
+        let target = match crate::target(<Handler::Api as ClientApi>::id(), handler.instance()) {
+            Ok(target) => target,
+            Err(e) => {
+                println!("Not handling {}: {e}", <Handler::Api as ClientApi>::id());
+                return None;
+            }
+        };
+        let (endpoint, peer_schemas) = self.runner.route(
+            ApiSpecifier::Handles(target),
+            <Handler::Api as ClientApi>::schema(),
+            handler.replay_history(),
+        );
+        let (reply_sink, messages) = endpoint.split();
+
+        Some((reply_sink, Arc::new(Mutex::new(messages)), peer_schemas))
+    }
+
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.runner.connection_state()
+    }
+
+    pub fn build(self) -> (Runner, Canceller) {
+        (self.runner, Canceller::new(self.cancel))
+    }
+}
+
+async fn handle_messages<Handler>(
+    mut handler: Handler,
+    reply_sink: Sender<Outgoing>,
+    messages: Inbox,
+) where
+    Handler: ClientApiHandler,
+{
+    loop {
+        let message = match messages.lock().await.recv().await {
+            Some(message) => message,
+            None => break,
+        };
+
+        let content = match serde_json::from_str(&message.content) {
+            Ok(content) => content,
+            Err(e) => {
+                println!(
+                    "Skipping {} message this handler doesn't understand: {e}",
+                    message.target
+                );
+                continue;
+            }
+        };
+
+        let envelope = message.envelope();
+        let trace_id = envelope.trace();
+
+        let result = trace::handling(envelope.clone(), async {
+            match message.correlation_id {
+                Some(correlation_id) => {
+                    let reply = handler.handle_enveloped_request(envelope, content).await?;
+
+                    // Waiting for room in the outbox holds the handler to the pace the
+                    // connection can keep up with.
+                    if let Some(reply) = reply {
+                        reply_sink
+                            .send(Outgoing::new(Message {
+                                correlation_id: Some(correlation_id),
+                                trace_id: Some(trace_id),
+                                ..Message::new(
+                                    message.target,
+                                    serde_json::to_string(&reply).unwrap(),
+                                )
+                            }))
+                            .await
+                            .unwrap_or_default();
+                    }
+
+                    Ok(())
+                }
+                None => handler.handle_enveloped_message(envelope, content).await,
+            }
+        })
+        .await;
+
+        if let Err(e) = result {
+            handler.on_error(e);
+        }
+    }
+}
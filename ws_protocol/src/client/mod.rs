@@ -1,7 +1,6 @@
 mod mpsc;
-use mpsc::*;
 
-use std::marker::PhantomData;
+use std::{future::Future, marker::PhantomData};
 
 use async_trait::async_trait;
 
@@ -11,23 +10,105 @@ pub use ws_task::*;
 mod runner;
 pub use runner::*;
 
+mod connection;
+pub use connection::*;
+
 mod canceller;
 pub use canceller::*;
 
-use tokio::sync::oneshot;
+mod config;
+pub use config::*;
+
+mod request;
+pub use request::RequestError;
+use request::*;
+
+mod outbox;
+use outbox::*;
+pub use outbox::{Delivery, OutboxPolicy, SendError};
+
+mod reconnect;
+pub use reconnect::*;
+
+mod state;
+pub use state::*;
 
-use crate::{message::Message, ApiSpecifier};
+mod tls;
+
+mod trace;
+
+use tokio::sync::{oneshot, watch};
+
+use crate::{message::Message, schema, ApiSchema, DeliveryStatus, Envelope, InvalidInstance};
 
 pub trait ClientApi: Send + Sync {
     type Message: Send + Sync + serde::Serialize + for<'de> serde::Deserialize<'de>;
+    type Reply: Send + Sync + serde::Serialize + for<'de> serde::Deserialize<'de> + 'static;
 
     fn id() -> &'static str;
+
+    fn schema_version() -> u32 {
+        1
+    }
+
+    fn schema() -> ApiSchema {
+        ApiSchema {
+            version: Self::schema_version(),
+            variants: schema::variant_names::<Self::Message>(),
+        }
+    }
 }
 
+#[async_trait]
 pub trait ClientApiHandler: Send + Sync {
     type Api: ClientApi;
+    type Error: std::fmt::Display + Send;
+
+    async fn handle_message(
+        &mut self,
+        message: <Self::Api as ClientApi>::Message,
+    ) -> Result<(), Self::Error>;
+
+    async fn handle_request(
+        &mut self,
+        message: <Self::Api as ClientApi>::Message,
+    ) -> Result<Option<<Self::Api as ClientApi>::Reply>, Self::Error> {
+        self.handle_message(message).await.map(|_| None)
+    }
+
+    // Override these instead to see who sent a message, when, and which trace it's part of.
+    async fn handle_enveloped_message(
+        &mut self,
+        _envelope: Envelope,
+        message: <Self::Api as ClientApi>::Message,
+    ) -> Result<(), Self::Error> {
+        self.handle_message(message).await
+    }
+
+    async fn handle_enveloped_request(
+        &mut self,
+        _envelope: Envelope,
+        message: <Self::Api as ClientApi>::Message,
+    ) -> Result<Option<<Self::Api as ClientApi>::Reply>, Self::Error> {
+        self.handle_request(message).await
+    }
+
+    fn instance(&self) -> Option<&str> {
+        None
+    }
+
+    // Ask the server to replay the history it keeps for this target on every (re)connect.
+    // Replayed messages are marked in their envelope.
+    fn replay_history(&self) -> bool {
+        false
+    }
 
-    fn handle_message(&mut self, message: <Self::Api as ClientApi>::Message);
+    fn on_error(&mut self, error: Self::Error) {
+        println!(
+            "Failed to handle {} message: {error}",
+            <Self::Api as ClientApi>::id()
+        );
+    }
 }
 
 pub type WsClientComponents<Api> = (WsClient<Api>, Runner, Canceller);
@@ -37,7 +118,12 @@ where
     Api: ClientApi,
 {
     _api: PhantomData<Api>,
-    emitter_endpoint: Endpoint,
+    target: String,
+    outbox: Outbox,
+    pending: PendingRequests,
+    deliveries: PendingDeliveries,
+    request_timeout: std::time::Duration,
+    peer_schemas: watch::Receiver<Vec<ApiSchema>>,
 }
 
 impl<Api> WsClient<Api>
@@ -45,28 +131,196 @@ where
     Api: ClientApi,
 {
     pub fn new() -> WsClientComponents<Api> {
-        let (emitter_endpoint, handler_endpoint) = mpsc::dual_channel();
-        let (cancel, on_cancel) = oneshot::channel();
+        Self::with_config(ClientConfig::from_env())
+    }
 
-        (
-            WsClient {
-                _api: PhantomData,
-                emitter_endpoint,
-            },
-            Runner::new(
-                ApiSpecifier::Emits(Api::id().into()),
-                handler_endpoint,
-                on_cancel,
-            ),
-            Canceller::new(cancel),
-        )
+    pub fn with_config(config: ClientConfig) -> WsClientComponents<Api> {
+        let mut connection = WsConnection::with_config(config);
+        let client = connection.client();
+        let (runner, canceller) = connection.build();
+
+        (client, runner, canceller)
+    }
+
+    pub fn for_instance(instance: &str) -> Result<WsClientComponents<Api>, InvalidInstance> {
+        Self::with_config_for_instance(ClientConfig::from_env(), Some(instance))
+    }
+
+    pub fn with_config_for_instance(
+        config: ClientConfig,
+        instance: Option<&str>,
+    ) -> Result<WsClientComponents<Api>, InvalidInstance> {
+        let mut connection = WsConnection::with_config(config);
+        let client = connection.instance_client(instance)?;
+        let (runner, canceller) = connection.build();
+
+        Ok((client, runner, canceller))
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.outbox.state()
+    }
+
+    pub fn peer_schemas(&self) -> watch::Receiver<Vec<ApiSchema>> {
+        self.peer_schemas.clone()
+    }
+
+    fn unsupported_variant(&self, message: &Api::Message) -> Option<String> {
+        let peer_schemas = self.peer_schemas.borrow();
+        let variant = schema::variant_name(message)?;
+
+        match peer_schemas.is_empty() || peer_schemas.iter().any(|s| s.understands(&variant)) {
+            true => None,
+            false => Some(variant),
+        }
+    }
+
+    fn message(&self, message: &Api::Message) -> Message {
+        Message {
+            trace_id: trace::current_trace(),
+            ..Message::new(self.target.clone(), serde_json::to_string(message).unwrap())
+        }
     }
 
     pub fn send(&self, message: Api::Message) {
-        self.emitter_endpoint.send(Message {
-            target: Api::id().into(),
-            content: serde_json::to_string(&message).unwrap(),
-        });
+        if let Err(e) = self.try_send(message) {
+            println!("Couldn't send {} message: {e}", self.target);
+        }
+    }
+
+    pub fn try_send(&self, message: Api::Message) -> Result<Delivery, SendError> {
+        if let Some(variant) = self.unsupported_variant(&message) {
+            return Err(SendError::Unsupported(variant));
+        }
+
+        self.outbox.send(self.message(&message))
+    }
+
+    pub fn send_confirmed(
+        &self,
+        message: Api::Message,
+    ) -> impl Future<Output = Result<DeliveryStatus, RequestError>> {
+        let delivery_id = rand::random();
+        let (status_tx, status_rx) = oneshot::channel();
+
+        let delivery = match self.unsupported_variant(&message) {
+            Some(variant) => Err(RequestError::Unsupported(variant)),
+            None => {
+                self.deliveries
+                    .lock()
+                    .unwrap()
+                    .insert(delivery_id, status_tx);
+
+                self.outbox
+                    .send(Message {
+                        delivery_id: Some(delivery_id),
+                        ..self.message(&message)
+                    })
+                    .map_err(RequestError::from)
+            }
+        };
+
+        let deliveries = self.deliveries.clone();
+        let request_timeout = self.request_timeout;
+        async move {
+            let status = match delivery {
+                Ok(Delivery::Sent | Delivery::Queued) => {
+                    tokio::time::timeout(request_timeout, status_rx).await
+                }
+                Ok(Delivery::Dropped) => {
+                    deliveries.lock().unwrap().remove(&delivery_id);
+                    return Err(RequestError::NotConnected);
+                }
+                Err(e) => {
+                    deliveries.lock().unwrap().remove(&delivery_id);
+                    return Err(e);
+                }
+            };
+            deliveries.lock().unwrap().remove(&delivery_id);
+
+            match status {
+                Ok(Ok(status)) => Ok(status),
+                _ => Err(RequestError::Timeout),
+            }
+        }
+    }
+
+    pub fn request(
+        &self,
+        message: Api::Message,
+    ) -> impl Future<Output = Result<Api::Reply, RequestError>> {
+        // Drawn apart from the correlation id, since delivery ids share one map with the ones
+        // send_confirmed draws.
+        let correlation_id = rand::random();
+        let delivery_id = rand::random();
+        let (reply_tx, mut reply_rx) = oneshot::channel();
+        let (status_tx, mut status_rx) = oneshot::channel();
+
+        let delivery = match self.unsupported_variant(&message) {
+            Some(variant) => Err(RequestError::Unsupported(variant)),
+            None => {
+                self.pending
+                    .lock()
+                    .unwrap()
+                    .insert(correlation_id, reply_tx);
+                self.deliveries
+                    .lock()
+                    .unwrap()
+                    .insert(delivery_id, status_tx);
+
+                self.outbox
+                    .send(Message {
+                        correlation_id: Some(correlation_id),
+                        delivery_id: Some(delivery_id),
+                        ..self.message(&message)
+                    })
+                    .map_err(RequestError::from)
+            }
+        };
+
+        let pending = self.pending.clone();
+        let deliveries = self.deliveries.clone();
+        let request_timeout = self.request_timeout;
+        async move {
+            let forget = || {
+                pending.lock().unwrap().remove(&correlation_id);
+                deliveries.lock().unwrap().remove(&delivery_id);
+            };
+
+            let reply = match delivery {
+                Ok(Delivery::Sent | Delivery::Queued) => {
+                    tokio::time::timeout(request_timeout, async {
+                        tokio::select! {
+                            reply = &mut reply_rx => reply.map_err(|_| RequestError::Timeout),
+                            Some(e) = async {
+                                (&mut status_rx).await.ok().and_then(RequestError::undelivered)
+                            } => Err(e),
+                        }
+                    })
+                    .await
+                }
+                Ok(Delivery::Dropped) => {
+                    forget();
+                    return Err(RequestError::NotConnected);
+                }
+                Err(e) => {
+                    forget();
+                    return Err(e);
+                }
+            };
+            forget();
+
+            match reply {
+                Ok(Ok(content)) => serde_json::from_str(&content)
+                    .map_err(|e| RequestError::InvalidReply(e.to_string())),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(RequestError::Timeout),
+            }
+        }
     }
 }
 
@@ -77,9 +331,9 @@ where
     Api: ClientApi,
     Handler: ClientApiHandler<Api = Api> + 'static,
 {
-    handler: Handler,
-    handler_endpoint: Endpoint,
+    _handler: PhantomData<Handler>,
     runner: Runner,
+    peer_schemas: watch::Receiver<Vec<ApiSchema>>,
 }
 
 impl<Api, Handler> WsClientHandler<Api, Handler>
@@ -88,22 +342,34 @@ where
     Handler: ClientApiHandler<Api = Api> + 'static,
 {
     pub fn new(handler: Handler) -> WsClientHandlerComponents<Api, Handler> {
-        let (emitter_endpoint, handler_endpoint) = mpsc::dual_channel();
-        let (cancel, on_cancel) = oneshot::channel();
+        Self::with_config(handler, ClientConfig::from_env())
+    }
+
+    pub fn with_config(
+        handler: Handler,
+        config: ClientConfig,
+    ) -> WsClientHandlerComponents<Api, Handler> {
+        let mut connection = WsConnection::with_config(config);
+        let peer_schemas = connection.route_handler(handler);
+        let (runner, canceller) = connection.build();
 
         (
             WsClientHandler {
-                handler,
-                handler_endpoint,
-                runner: Runner::new(
-                    ApiSpecifier::Handles(Api::id().into()),
-                    emitter_endpoint,
-                    on_cancel,
-                ),
+                _handler: PhantomData,
+                runner,
+                peer_schemas,
             },
-            Canceller::new(cancel),
+            canceller,
         )
     }
+
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.runner.connection_state()
+    }
+
+    pub fn peer_schemas(&self) -> watch::Receiver<Vec<ApiSchema>> {
+        self.peer_schemas.clone()
+    }
 }
 
 #[async_trait]
@@ -113,23 +379,6 @@ where
     Handler: ClientApiHandler<Api = Api> + 'static,
 {
     async fn run(self, addr: String) {
-        let WsClientHandler {
-            mut handler,
-            mut handler_endpoint,
-            runner,
-        } = self;
-
-        let message_task = async move {
-            handler_endpoint
-                .handle(|message| {
-                    handler.handle_message(serde_json::from_str(&message.content).unwrap())
-                })
-                .await;
-        };
-
-        tokio::select! {
-            _ = tokio::spawn(message_task) => {}
-            _ = runner.run(addr) => {}
-        }
+        self.runner.run(addr).await
     }
 }
@@ -0,0 +1,179 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{
+    mpsc::{error::TrySendError, Receiver, Sender},
+    watch,
+};
+
+use crate::message::{self, Message};
+
+use super::ConnectionState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxPolicy {
+    Drop,
+    Queue { ttl: Duration },
+    Reject,
+}
+
+impl OutboxPolicy {
+    pub fn parse(value: &str) -> Option<OutboxPolicy> {
+        match value.split_once(':') {
+            Some(("queue", secs)) => secs.parse().ok().map(|secs| OutboxPolicy::Queue {
+                ttl: Duration::from_secs(secs),
+            }),
+            Some(_) => None,
+            None => match value {
+                "drop" => Some(OutboxPolicy::Drop),
+                "reject" => Some(OutboxPolicy::Reject),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Sent,
+    Queued,
+    Dropped,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    NotConnected,
+    OutboxFull,
+    Unsupported(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotConnected => write!(f, "Not connected to the WS server."),
+            Self::OutboxFull => write!(f, "Too many messages are waiting to be sent."),
+            Self::Unsupported(variant) => {
+                write!(f, "No connected peer understands {variant} messages.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+#[derive(Debug)]
+pub struct Outgoing {
+    pub message: Message,
+    pub expires_at: Option<Instant>,
+}
+
+impl Outgoing {
+    pub fn new(message: Message) -> Outgoing {
+        Outgoing {
+            message,
+            expires_at: None,
+        }
+    }
+
+    // Only queued messages outlive the connection they were sent on.
+    fn is_live(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at > Instant::now())
+            .unwrap_or(false)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= Instant::now())
+            .unwrap_or(false)
+    }
+}
+
+pub struct Outbox {
+    policy: OutboxPolicy,
+    state: watch::Receiver<ConnectionState>,
+    message_sink: Sender<Outgoing>,
+}
+
+impl Outbox {
+    pub fn new(
+        policy: OutboxPolicy,
+        state: watch::Receiver<ConnectionState>,
+        message_sink: Sender<Outgoing>,
+    ) -> Outbox {
+        Outbox {
+            policy,
+            state,
+            message_sink,
+        }
+    }
+
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    pub fn send(&self, message: Message) -> Result<Delivery, SendError> {
+        // The deadline travels with the message too, so the server won't hold on to it for
+        // longer than it's worth delivering.
+        let (expires_at, message) = match self.policy {
+            OutboxPolicy::Queue { ttl } => (
+                Some(Instant::now() + ttl),
+                Message {
+                    expires_at: Some(message::now() + ttl.as_millis() as u64),
+                    ..message
+                },
+            ),
+            _ => (None, message),
+        };
+
+        let delivery = match (self.state.borrow().is_connected(), self.policy) {
+            (true, _) => Delivery::Sent,
+            (false, OutboxPolicy::Queue { .. }) => Delivery::Queued,
+            (false, OutboxPolicy::Drop) => return Ok(Delivery::Dropped),
+            (false, OutboxPolicy::Reject) => return Err(SendError::NotConnected),
+        };
+
+        self.message_sink
+            .try_send(Outgoing {
+                message,
+                expires_at,
+            })
+            .map_err(|e| match e {
+                TrySendError::Full(_) => SendError::OutboxFull,
+                TrySendError::Closed(_) => SendError::NotConnected,
+            })?;
+
+        Ok(delivery)
+    }
+}
+
+// Messages keep arriving while reconnecting, so only the newest of them are kept.
+pub fn drain(
+    from_client: &mut Receiver<Outgoing>,
+    backlog: &mut VecDeque<Outgoing>,
+    capacity: usize,
+) {
+    while let Ok(outgoing) = from_client.try_recv() {
+        backlog.push_back(outgoing);
+    }
+
+    backlog.retain(|outgoing| {
+        let live = outgoing.is_live();
+        if !live {
+            println!(
+                "Discarding {} message that wasn't sent before the connection dropped.",
+                outgoing.message.target
+            );
+        }
+
+        live
+    });
+
+    let overflow = backlog.len().saturating_sub(capacity);
+    if overflow > 0 {
+        println!("Discarding {overflow} message(s) that didn't fit in the backlog.");
+        backlog.drain(..overflow);
+    }
+}
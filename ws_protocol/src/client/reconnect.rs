@@ -0,0 +1,114 @@
+use std::{sync::Arc, time::Duration};
+
+use rand::Rng;
+
+#[derive(Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub max_attempts: Option<u32>,
+    // How many times to retry at the initial delay after the server says it's restarting,
+    // before backing off as usual in case it doesn't come back.
+    pub restart_attempts: u32,
+    pub on_give_up: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl ReconnectPolicy {
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_delay.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let jitter = match jitter > 0.0 {
+            true => delay * jitter * rand::thread_rng().gen_range(-1.0..=1.0),
+            false => 0.0,
+        };
+
+        Duration::from_secs_f64((delay + jitter).max(0.0))
+    }
+
+    pub fn gives_up_after(&self, failures: u32) -> bool {
+        self.max_attempts
+            .map(|max_attempts| failures >= max_attempts)
+            .unwrap_or(false)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+            restart_attempts: 5,
+            on_give_up: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for ReconnectPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectPolicy")
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("multiplier", &self.multiplier)
+            .field("jitter", &self.jitter)
+            .field("max_attempts", &self.max_attempts)
+            .field("restart_attempts", &self.restart_attempts)
+            .field("on_give_up", &self.on_give_up.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    fn policy(jitter: f64) -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter,
+            ..ReconnectPolicy::default()
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_max() {
+        let policy = policy(0.0);
+
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(4), Duration::from_secs(8));
+        assert_eq!(policy.delay(5), Duration::from_secs(10));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn keeps_jitter_within_its_fraction() {
+        let policy = policy(0.5);
+
+        for _ in 0..100 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(6));
+        }
+
+        // Jitter over 1 is clamped, so the delay never goes negative.
+        let policy = ReconnectPolicy {
+            jitter: 5.0,
+            ..policy
+        };
+        for _ in 0..100 {
+            assert!(policy.delay(1) <= Duration::from_secs(2));
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{mpsc::Receiver, oneshot};
+
+use crate::{message::Message, DeliveryStatus};
+
+use super::SendError;
+
+pub type PendingRequests = Arc<Mutex<HashMap<u64, oneshot::Sender<String>>>>;
+
+pub type PendingDeliveries = Arc<Mutex<HashMap<u64, oneshot::Sender<DeliveryStatus>>>>;
+
+pub async fn route_replies(mut replies: Receiver<Message>, pending: PendingRequests) {
+    loop {
+        let message = match replies.recv().await {
+            Some(msg) => msg,
+            None => break,
+        };
+
+        let correlation_id = match message.correlation_id {
+            Some(correlation_id) => correlation_id,
+            None => continue,
+        };
+
+        if let Some(reply_tx) = pending.lock().unwrap().remove(&correlation_id) {
+            reply_tx.send(message.content).unwrap_or_default();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    NotConnected,
+    OutboxFull,
+    Timeout,
+    NoRoute,
+    Forbidden,
+    RateLimited,
+    TooLarge,
+    InvalidReply(String),
+    Unsupported(String),
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotConnected => write!(f, "Not connected to the WS server."),
+            Self::OutboxFull => write!(f, "Too many messages are waiting to be sent."),
+            Self::Timeout => write!(f, "Timed out waiting for a reply."),
+            Self::NoRoute => write!(f, "No handler is connected."),
+            Self::Forbidden => write!(f, "Not allowed to send to this target."),
+            Self::RateLimited => write!(f, "Sending faster than the server allows."),
+            Self::TooLarge => write!(f, "Larger than the server allows."),
+            Self::InvalidReply(e) => write!(f, "Received an invalid reply: {e}"),
+            Self::Unsupported(variant) => {
+                write!(f, "No connected peer understands {variant} messages.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl RequestError {
+    // Why a request won't be answered, if the server didn't pass it on.
+    pub(crate) fn undelivered(status: DeliveryStatus) -> Option<RequestError> {
+        match status {
+            DeliveryStatus::Delivered(_) | DeliveryStatus::Stored => None,
+            DeliveryStatus::NoRoute => Some(RequestError::NoRoute),
+            DeliveryStatus::Forbidden => Some(RequestError::Forbidden),
+            DeliveryStatus::RateLimited => Some(RequestError::RateLimited),
+            DeliveryStatus::TooLarge => Some(RequestError::TooLarge),
+        }
+    }
+}
+
+impl From<SendError> for RequestError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::NotConnected => RequestError::NotConnected,
+            SendError::OutboxFull => RequestError::OutboxFull,
+            SendError::Unsupported(variant) => RequestError::Unsupported(variant),
+        }
+    }
+}
@@ -1,146 +1,526 @@
-use std::time::Duration;
+use std::{collections::VecDeque, future::Future, pin::Pin, sync::Arc, time::Instant};
 
 use async_trait::async_trait;
-use futures_util::{SinkExt, StreamExt, TryStreamExt};
+use futures_util::{future, Sink, SinkExt, StreamExt};
 use tokio::sync::{
-    mpsc::{UnboundedReceiver, UnboundedSender},
-    oneshot,
+    mpsc::{self, error::TrySendError, Receiver, Sender},
+    oneshot, watch,
 };
+use tokio_tungstenite::Connector;
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use ws_protocol_version::prove;
 
-use crate::{message::Message, ApiSpecifier, Handshake, WsTask};
+use crate::{
+    control::Control, heartbeat::LastSeen, message::Message, ApiSchema, ApiSpecifier, Codec,
+    Handshake, Registration, ServerIdentification, Transport, WsTask, PROTOCOL_VERSION,
+};
+
+use super::{
+    mpsc::Endpoint,
+    outbox::{self, Outgoing},
+    request::PendingDeliveries,
+    tls, ClientConfig, ConnectionState, InboxPolicy,
+};
 
-use super::mpsc::Endpoint;
+#[derive(Clone)]
+struct Route {
+    registration: Registration,
+    to_client: mpsc::Sender<Message>,
+    peer_schemas: Arc<watch::Sender<Vec<ApiSchema>>>,
+}
 
 pub struct Runner {
-    api_specifier: ApiSpecifier,
-    endpoint: Endpoint,
+    config: ClientConfig,
+    routes: Vec<Route>,
+    message_sink: Sender<Outgoing>,
+    message_stream: Receiver<Outgoing>,
     on_cancel: oneshot::Receiver<()>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    deliveries: PendingDeliveries,
+    tasks: Vec<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    transport: Option<Arc<dyn Transport>>,
 }
 
 impl Runner {
-    pub fn new(
-        api_specifier: ApiSpecifier,
-        endpoint: Endpoint,
-        on_cancel: oneshot::Receiver<()>,
-    ) -> Runner {
+    pub fn new(config: ClientConfig, on_cancel: oneshot::Receiver<()>) -> Runner {
+        let (message_sink, message_stream) = mpsc::channel(config.outbox_capacity);
+
         Runner {
-            api_specifier,
-            endpoint,
+            config,
+            routes: vec![],
+            message_sink,
+            message_stream,
             on_cancel,
+            state: Arc::new(watch::channel(ConnectionState::Connecting).0),
+            deliveries: PendingDeliveries::default(),
+            tasks: vec![],
+            transport: None,
         }
     }
+
+    pub(crate) fn route(
+        &mut self,
+        api_specifier: ApiSpecifier,
+        schema: ApiSchema,
+        replay: bool,
+    ) -> (Endpoint<Outgoing, Message>, watch::Receiver<Vec<ApiSchema>>) {
+        if self
+            .routes
+            .iter()
+            .any(|route| route.registration.api_specifier.target() == api_specifier.target())
+        {
+            panic!(
+                "{} is already registered on this connection",
+                api_specifier.target()
+            );
+        }
+
+        let (to_client, message_stream) = mpsc::channel(self.config.inbox_capacity);
+        let peer_schemas = Arc::new(watch::channel(vec![]).0);
+        let peer_schemas_rx = peer_schemas.subscribe();
+
+        self.routes.push(Route {
+            registration: Registration {
+                api_specifier,
+                schema,
+                replay,
+            },
+            to_client,
+            peer_schemas,
+        });
+
+        (
+            Endpoint::new(self.message_sink.clone(), message_stream),
+            peer_schemas_rx,
+        )
+    }
+
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    pub(crate) fn deliveries(&self) -> PendingDeliveries {
+        self.deliveries.clone()
+    }
+
+    pub(crate) fn add_task<F>(&mut self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.push(Box::pin(task));
+    }
+
+    pub fn with_task<F>(mut self, task: F) -> Runner
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.add_task(task);
+        self
+    }
+
+    pub fn with_transport<T>(mut self, transport: T) -> Runner
+    where
+        T: Transport + 'static,
+    {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
 }
 
 #[async_trait]
 impl WsTask for Runner {
     async fn run(self, addr: String) {
         let Runner {
-            api_specifier,
-            endpoint,
+            config,
+            routes,
+            message_stream,
             on_cancel,
+            state,
+            deliveries,
+            tasks,
+            transport,
+            ..
         } = self;
-        let (to_client, mut from_client) = endpoint.split();
+
+        let connector =
+            tls::connector(&config.tls).unwrap_or_else(|e| panic!("Error loading TLS config: {e}"));
+        let transport = transport.unwrap_or_else(|| crate::transport_for(&addr));
 
         let cancel_task = tokio::spawn(on_cancel);
+        let (closing_tx, mut closing) = watch::channel(false);
+        let shutdown_timeout = config.shutdown_timeout;
 
-        // TODO: If there is no server connection, and a message is sent by the client, it
-        // TODO: will be trapped in the mpsc channel until a connection is made, at which point
-        // TODO: it will be send immediately to the server. I may need to find a way
-        // TODO: to empty the channel before each connection attempt. Or otherwise
-        // TODO: allow the client to ask if there is a connection first.
+        let link_state = state.clone();
         let ws_task = async move {
+            let mut link = Link {
+                routes,
+                from_client: message_stream,
+                backlog: VecDeque::new(),
+                state: link_state,
+                deliveries,
+            };
+
+            let mut failures = 0;
+            let mut restart_attempts = 0;
+
             loop {
-                if let Err(e) = connect(&addr, &api_specifier, &to_client, &mut from_client).await {
-                    println!("WS connection failure: {e}");
+                outbox::drain(
+                    &mut link.from_client,
+                    &mut link.backlog,
+                    config.outbox_capacity,
+                );
+                link.state.send_replace(ConnectionState::Connecting);
+
+                let result = connect(
+                    &addr,
+                    &config,
+                    &*transport,
+                    &connector,
+                    &mut link,
+                    &mut closing,
+                )
+                .await;
+                for route in &link.routes {
+                    route.peer_schemas.send_replace(vec![]);
+                }
+
+                if *closing.borrow() {
+                    break;
+                }
+
+                if link.state.borrow().is_connected() {
+                    restart_attempts = 0;
+                }
+
+                match result {
+                    Err(Error::HeartbeatTimeout) => {
+                        println!("Server missed its heartbeat. Reconnecting...");
+                        failures = 0;
+                        continue;
+                    }
+                    Err(Error::Closed(Some(frame))) if frame.code == CloseCode::Restart => {
+                        println!("Server is restarting ({}). Reconnecting...", frame.reason);
+                        restart_attempts = config.reconnect.restart_attempts;
+                        failures = 0;
+                        continue;
+                    }
+                    Err(Error::Closed(Some(frame))) if frame.code == CloseCode::Normal => {
+                        println!("Server shut down ({}).", frame.reason)
+                    }
+                    Err(e) => println!("WS connection failure: {e}"),
+                    Ok(()) => {}
+                }
+
+                failures = match link.state.borrow().is_connected() {
+                    true => 0,
+                    false => failures + 1,
+                };
+
+                if config.reconnect.gives_up_after(failures) {
+                    println!("No connection after {failures} attempts. Giving up.");
+                    link.state.send_replace(ConnectionState::GaveUp);
+
+                    if let Some(on_give_up) = &config.reconnect.on_give_up {
+                        on_give_up();
+                    }
+
+                    break;
                 }
 
-                println!("No connection. Trying again in 5 seconds...");
-                tokio::time::sleep(Duration::from_secs(5)).await;
+                // A restarting server is expected back shortly, so retry at the initial delay
+                // for a while instead of backing off further.
+                let retry_delay = match restart_attempts {
+                    0 => config.reconnect.delay(failures),
+                    _ => {
+                        restart_attempts -= 1;
+                        config.reconnect.delay(1)
+                    }
+                };
+                link.state
+                    .send_replace(ConnectionState::Backoff(Instant::now() + retry_delay));
+
+                println!(
+                    "No connection. Trying again in {:.1} seconds...",
+                    retry_delay.as_secs_f64()
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(retry_delay) => {}
+                    _ = closing.changed() => break,
+                }
             }
         };
 
+        let tasks = async move {
+            future::join_all(tasks).await;
+            std::future::pending::<()>().await
+        };
+
+        tokio::pin!(ws_task);
+        let mut tasks = tokio::spawn(tasks);
+
         tokio::select! {
-            _ = cancel_task => {},
-            _ = ws_task => {},
+            _ = cancel_task => {
+                closing_tx.send_replace(true);
+                tokio::time::timeout(shutdown_timeout, &mut ws_task)
+                    .await
+                    .unwrap_or_default();
+                state.send_replace(ConnectionState::Cancelled);
+            },
+            _ = &mut ws_task => {},
+            _ = &mut tasks => {},
         };
+
+        tasks.abort();
     }
 }
 
+struct Link {
+    routes: Vec<Route>,
+    from_client: Receiver<Outgoing>,
+    backlog: VecDeque<Outgoing>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    deliveries: PendingDeliveries,
+}
+
 async fn connect(
-    addr: &String,
-    api_specifier: &ApiSpecifier,
-    to_client: &UnboundedSender<Message>,
-    from_client: &mut UnboundedReceiver<Message>,
+    addr: &str,
+    config: &ClientConfig,
+    transport: &dyn Transport,
+    connector: &Option<Connector>,
+    link: &mut Link,
+    closing: &mut watch::Receiver<bool>,
 ) -> Result<(), Error> {
-    let (ws_stream, _) = tokio_tungstenite::connect_async(addr).await?;
+    let stream = transport.connect(addr).await?;
+    let (ws_stream, _) = tokio_tungstenite::client_async_tls_with_config(
+        transport.request_url(addr),
+        stream,
+        None,
+        connector.clone(),
+    )
+    .await?;
     println!("Established WS connection.");
 
     let (mut write, read) = ws_stream.split();
 
     let mut read = Box::pin(read);
-    match read.next().await {
-        Some(Ok(message)) => {
-            if let Ok(Handshake::ServerIdentification) = message.try_into() {
+    let challenge = match read.next().await {
+        Some(Ok(message)) => match message.try_into() {
+            Ok(Handshake::ServerIdentification(ServerIdentification {
+                challenge,
+                protocol_version,
+            })) => {
+                if protocol_version != PROTOCOL_VERSION {
+                    return Err(Error::IncompatibleProtocol(protocol_version));
+                }
                 println!("Bjorn server identified.");
-            } else {
-                return Err(Error::InvalidHandshakeToken);
+                challenge
             }
-        }
+            _ => return Err(Error::InvalidHandshakeToken),
+        },
         _ => return Err(Error::InvalidHandshakeToken),
-    }
+    };
 
     println!("Sending handshake response...");
     write
-        .send(Handshake::ClientIdentification(api_specifier.clone()).into())
+        .send(
+            Handshake::ClientIdentification {
+                apis: link
+                    .routes
+                    .iter()
+                    .map(|route| route.registration.clone())
+                    .collect(),
+                proof: config
+                    .secret
+                    .as_ref()
+                    .map(|secret| prove(secret, &challenge)),
+                protocol_version: PROTOCOL_VERSION,
+                identity: config.identity.clone(),
+                codecs: match config.codec {
+                    Codec::Json => vec![Codec::Json],
+                    codec => vec![codec, Codec::Json],
+                },
+            }
+            .into(),
+        )
         .await?;
 
-    println!("Bjorn handhsake complete.");
+    // Servers that predate codec negotiation simply accept, and only speak JSON.
+    let codec = match read.next().await {
+        Some(Ok(message)) => match message.try_into() {
+            Ok(Handshake::Accepted) => Codec::Json,
+            Ok(Handshake::Negotiated { codec }) => codec,
+            Ok(Handshake::Rejected(reason)) => return Err(Error::Rejected(reason)),
+            _ => return Err(Error::InvalidHandshakeToken),
+        },
+        _ => return Err(Error::InvalidHandshakeToken),
+    };
+
+    println!("Bjorn handshake complete.");
 
+    while let Some(outgoing) = link.backlog.pop_front() {
+        write_outgoing(&mut write, codec, outgoing, &mut link.backlog).await?;
+    }
+    link.state.send_replace(ConnectionState::Connected);
+
+    let heartbeat = config.heartbeat;
+    let last_seen = LastSeen::new();
+
+    let from_client = &mut link.from_client;
+    let backlog = &mut link.backlog;
+    let send_last_seen = last_seen.clone();
+    let mut send_closing = closing.clone();
     let send_task = async move {
+        let mut ticker = heartbeat.ticker();
         loop {
-            let message = match from_client.recv().await {
-                Some(msg) => msg,
-                None => break,
-            };
+            tokio::select! {
+                outgoing = from_client.recv() => match outgoing {
+                    Some(outgoing) => write_outgoing(&mut write, codec, outgoing, backlog).await?,
+                    None => return Ok(()),
+                },
+                _ = send_closing.changed() => {
+                    // Flush whatever the handlers already sent before saying goodbye.
+                    while let Ok(outgoing) = from_client.try_recv() {
+                        write_outgoing(&mut write, codec, outgoing, backlog).await?;
+                    }
+
+                    write
+                        .send(tungstenite::Message::Close(Some(CloseFrame {
+                            code: CloseCode::Normal,
+                            reason: "Client shutting down".into(),
+                        })))
+                        .await?;
+                    return Ok(());
+                },
+                _ = ticker.tick() => {
+                    if heartbeat.is_overdue(&send_last_seen) {
+                        return Err(Error::HeartbeatTimeout);
+                    }
 
-            write.send(message.into()).await.unwrap_or_default();
+                    write.send(tungstenite::Message::Ping(vec![])).await?;
+                }
+            }
         }
     };
 
-    let to_client = to_client.clone();
+    let routes = link.routes.clone();
+    let deliveries = link.deliveries.clone();
+    let inbox_policy = config.inbox_policy;
     let recv_task = async move {
-        read.try_for_each(|message| {
-            let to_client = to_client.clone();
-            async move {
-                let message = match message.clone().try_into() {
-                    Ok(msg) => msg,
-                    Err(_) => {
-                        println!("Received invalid WS message: {message}");
-                        return Err(tungstenite::Error::ConnectionClosed);
-                    }
-                };
+        while let Some(message) = read.next().await {
+            let message = message?;
+            last_seen.touch();
 
-                to_client.send(message).unwrap_or_default();
+            if message.is_ping() || message.is_pong() {
+                continue;
+            }
 
-                Ok(())
+            if let tungstenite::Message::Close(frame) = message {
+                return Err(Error::Closed(frame));
             }
-        })
-        .await
-        .unwrap_or_default();
+
+            let find_route = |target: &str| {
+                routes
+                    .iter()
+                    .find(|route| route.registration.api_specifier.target() == target)
+            };
+
+            if let Ok(msg) = Message::try_from(message.clone()) {
+                let Some(route) = find_route(&msg.target) else {
+                    println!("Received message for unregistered target {}", msg.target);
+                    continue;
+                };
+                // Waiting on one slow inbox would hold up pongs, replies and every other route
+                // behind it, so a message that doesn't fit is dealt with by the inbox policy.
+                match route.to_client.try_send(msg) {
+                    Ok(()) | Err(TrySendError::Closed(_)) => {}
+                    Err(TrySendError::Full(msg)) => match inbox_policy {
+                        InboxPolicy::DropNewest => {
+                            println!("Dropping message for {}: its inbox is full.", msg.target)
+                        }
+                        InboxPolicy::Disconnect => return Err(Error::InboxFull(msg.target)),
+                    },
+                }
+                continue;
+            }
+
+            match message.clone().try_into() {
+                Ok(Control::PeerSchemas { target, schemas }) => {
+                    if let Some(route) = find_route(&target) {
+                        route.peer_schemas.send_replace(schemas);
+                    }
+                }
+                Ok(Control::Delivery {
+                    delivery_id,
+                    status,
+                }) => {
+                    if let Some(status_tx) = deliveries.lock().unwrap().remove(&delivery_id) {
+                        status_tx.send(status).unwrap_or_default();
+                    }
+                }
+                Ok(Control::Observed { .. }) => {}
+                Err(_) => {
+                    println!("Received invalid WS message: {message}");
+                    break;
+                }
+            }
+        }
+
+        Ok(())
     };
 
+    let mut recv_task = tokio::spawn(recv_task);
     tokio::select! {
-        _ = send_task => {}
-        _ = tokio::spawn(recv_task) => {}
+        result = send_task => {
+            if result.is_ok() && *closing.borrow() {
+                // Wait for the server to acknowledge the close.
+                recv_task.await.unwrap_or(Ok(())).unwrap_or_default();
+            } else {
+                recv_task.abort();
+            }
+            result
+        },
+        result = &mut recv_task => result.unwrap_or(Ok(())),
+    }
+}
+
+// Messages can wait a long time in the backlog or behind a slow connect, so their TTL is
+// checked right before they're written. One that can't be written goes back in the backlog
+// to be retried once reconnected.
+async fn write_outgoing<S>(
+    write: &mut S,
+    codec: Codec,
+    outgoing: Outgoing,
+    backlog: &mut VecDeque<Outgoing>,
+) -> Result<(), Error>
+where
+    S: Sink<tungstenite::Message, Error = tungstenite::Error> + Unpin,
+{
+    if outgoing.is_expired() {
+        println!(
+            "Discarding {} message that wasn't sent before it expired.",
+            outgoing.message.target
+        );
+        return Ok(());
     }
 
-    Ok(())
+    match write.send(codec.encode(outgoing.message.clone())).await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            backlog.push_front(outgoing);
+            Err(e.into())
+        }
+    }
 }
 
 #[derive(Debug)]
 enum Error {
     TungsteniteError(tungstenite::Error),
     InvalidHandshakeToken,
+    Rejected(String),
+    IncompatibleProtocol(u32),
+    HeartbeatTimeout,
+    InboxFull(String),
+    Closed(Option<CloseFrame<'static>>),
 }
 
 impl std::fmt::Display for Error {
@@ -151,6 +531,19 @@ impl std::fmt::Display for Error {
             match self {
                 Self::TungsteniteError(e) => format!("Error in tungstenite: {e}"),
                 Self::InvalidHandshakeToken => "Server sent invalid handshake token.".into(),
+                Self::Rejected(reason) => format!("Server rejected handshake: {reason}"),
+                Self::HeartbeatTimeout => "Server missed its heartbeat.".into(),
+                Self::InboxFull(target) => format!("{target} couldn't keep up with its messages."),
+                Self::Closed(Some(frame)) => {
+                    format!(
+                        "Server closed the connection: {} ({})",
+                        frame.code, frame.reason
+                    )
+                }
+                Self::Closed(None) => "Server closed the connection.".into(),
+                Self::IncompatibleProtocol(version) => format!(
+                    "Server speaks protocol version {version}, client speaks {PROTOCOL_VERSION}"
+                ),
             }
         )
     }
@@ -158,6 +551,12 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::TungsteniteError(tungstenite::Error::Io(e))
+    }
+}
+
 impl From<tungstenite::Error> for Error {
     fn from(e: tungstenite::Error) -> Self {
         Error::TungsteniteError(e)
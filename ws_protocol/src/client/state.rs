@@ -0,0 +1,34 @@
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Backoff(Instant),
+    GaveUp,
+    Cancelled,
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, ConnectionState::Connected)
+    }
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connecting => write!(f, "connecting"),
+            Self::Connected => write!(f, "connected"),
+            Self::Backoff(next_attempt) => write!(
+                f,
+                "reconnecting in {}s",
+                next_attempt
+                    .saturating_duration_since(Instant::now())
+                    .as_secs()
+            ),
+            Self::GaveUp => write!(f, "gave up reconnecting"),
+            Self::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
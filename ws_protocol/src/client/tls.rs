@@ -0,0 +1,77 @@
+use std::{fs::File, io::BufReader, sync::Arc, time::SystemTime};
+
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, RootCertStore, ServerName,
+};
+use sha2::{Digest, Sha256};
+use tokio_tungstenite::Connector;
+
+use super::ClientTlsConfig;
+
+pub fn connector(config: &ClientTlsConfig) -> Result<Option<Connector>, String> {
+    if config.ca_path.is_none() && config.pinned_cert_sha256.is_none() {
+        return Ok(None);
+    }
+
+    let mut roots = RootCertStore::empty();
+    match &config.ca_path {
+        Some(path) => {
+            let file = File::open(path).map_err(|e| format!("Couldn't open {path}: {e}"))?;
+            let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+                .map_err(|e| format!("Couldn't read certificates from {path}: {e}"))?;
+
+            let (added, _) = roots.add_parsable_certificates(&certs);
+            if added == 0 {
+                return Err(format!("No usable CA certificates found in {path}"));
+            }
+        }
+        None => {
+            roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+        }
+    }
+
+    let mut client_config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    if let Some(fingerprint) = &config.pinned_cert_sha256 {
+        let fingerprint = hex::decode(fingerprint.replace(':', ""))
+            .map_err(|e| format!("Invalid pinned certificate fingerprint: {e}"))?;
+
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(PinnedCertVerifier(fingerprint)));
+    }
+
+    Ok(Some(Connector::Rustls(Arc::new(client_config))))
+}
+
+struct PinnedCertVerifier(Vec<u8>);
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(&end_entity.0).as_slice() == self.0.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "Server certificate doesn't match the pinned fingerprint".into(),
+            ))
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use std::future::Future;
+
+use crate::Envelope;
+
+tokio::task_local! {
+    static HANDLING: Envelope;
+}
+
+// Messages sent while a handler runs join the trace of the message it's handling.
+pub(crate) async fn handling<F: Future>(envelope: Envelope, future: F) -> F::Output {
+    HANDLING.scope(envelope, future).await
+}
+
+pub(crate) fn current_trace() -> Option<u64> {
+    HANDLING.try_with(Envelope::trace).ok()
+}
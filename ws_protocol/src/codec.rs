@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(feature = "client", feature = "server"))]
+use crate::message::Message;
+
+// How Message frames travel on a connection. Handshakes and control frames are always JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Codec {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl Codec {
+    pub fn parse(codec: &str) -> Option<Codec> {
+        match codec.trim().to_lowercase().as_str() {
+            "json" => Some(Codec::Json),
+            "msgpack" | "messagepack" => Some(Codec::MessagePack),
+            _ => None,
+        }
+    }
+
+    #[cfg(any(feature = "client", feature = "server"))]
+    pub(crate) fn encode(self, message: Message) -> tungstenite::Message {
+        match self {
+            Codec::Json => message.into(),
+            Codec::MessagePack => tungstenite::Message::Binary(message.to_msgpack()),
+        }
+    }
+}
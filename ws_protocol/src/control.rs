@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{message::Message, ApiSchema, ApiSpecifier};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Control {
+    PeerSchemas {
+        target: String,
+        schemas: Vec<ApiSchema>,
+    },
+    Delivery {
+        delivery_id: u64,
+        status: DeliveryStatus,
+    },
+    Observed {
+        from: ApiSpecifier,
+        message: Message,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    Delivered(usize),
+    Stored,
+    NoRoute,
+    // The sender's ACL entry doesn't allow it to send to the target.
+    Forbidden,
+    // Dropped for going over the server's limits.
+    RateLimited,
+    TooLarge,
+}
+
+impl TryFrom<tungstenite::Message> for Control {
+    type Error = String;
+
+    fn try_from(msg: tungstenite::Message) -> Result<Self, Self::Error> {
+        match msg {
+            tungstenite::Message::Text(json) => match serde_json::from_str(json.as_str()) {
+                Ok(control) => Ok(control),
+                Err(e) => Err(format!(
+                    "Error parsing control message: {e}\nMessage:\n{json}"
+                )),
+            },
+            msg => Err(format!("Cannot parse control message from {msg}")),
+        }
+    }
+}
+
+impl From<Control> for tungstenite::Message {
+    fn from(control: Control) -> Self {
+        tungstenite::Message::Text(serde_json::to_string(&control).unwrap())
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(45),
+        }
+    }
+}
+
+impl Heartbeat {
+    pub fn from_env() -> Heartbeat {
+        let default = Heartbeat::default();
+        let env_secs = |key| {
+            std::env::var(key)
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                // Ticking needs a non-zero interval, and a zero timeout would drop every peer.
+                .filter(|secs: &u64| *secs > 0)
+                .map(Duration::from_secs)
+        };
+
+        Heartbeat {
+            interval: env_secs("BJORN_WS_HEARTBEAT_INTERVAL").unwrap_or(default.interval),
+            timeout: env_secs("BJORN_WS_HEARTBEAT_TIMEOUT").unwrap_or(default.timeout),
+        }
+    }
+
+    pub(crate) fn ticker(&self) -> Interval {
+        let mut ticker = tokio::time::interval_at(Instant::now() + self.interval, self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker
+    }
+
+    pub(crate) fn is_overdue(&self, last_seen: &LastSeen) -> bool {
+        last_seen.0.lock().unwrap().elapsed() > self.timeout
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LastSeen(Arc<Mutex<Instant>>);
+
+impl LastSeen {
+    pub fn new() -> LastSeen {
+        LastSeen(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub fn touch(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+}
@@ -1,16 +1,108 @@
 use serde::{Deserialize, Serialize};
 
+pub use ws_protocol_version::{ObserverIdentification, ServerIdentification};
+
 #[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize, Clone)]
 pub enum ApiSpecifier {
     Emits(String),
     Handles(String),
 }
 
+impl ApiSpecifier {
+    pub fn target(&self) -> &str {
+        match self {
+            ApiSpecifier::Emits(target) | ApiSpecifier::Handles(target) => target,
+        }
+    }
+
+    pub fn api_id(&self) -> &str {
+        api_id(self.target())
+    }
+
+    pub fn instance(&self) -> Option<&str> {
+        self.target()
+            .split_once(INSTANCE_SEPARATOR)
+            .map(|(_, instance)| instance)
+    }
+
+    pub fn opposite(&self) -> ApiSpecifier {
+        match self {
+            ApiSpecifier::Emits(target) => ApiSpecifier::Handles(target.clone()),
+            ApiSpecifier::Handles(target) => ApiSpecifier::Emits(target.clone()),
+        }
+    }
+}
+
+pub const INSTANCE_SEPARATOR: char = '@';
+
+// Instance names usually come from configuration, so a bad one is the caller's to report
+// rather than a reason to panic.
+pub fn target(api_id: &str, instance: Option<&str>) -> Result<String, InvalidInstance> {
+    match instance {
+        Some(instance) if !is_valid_instance(instance) => Err(InvalidInstance(instance.into())),
+        Some(instance) => Ok(format!("{api_id}{INSTANCE_SEPARATOR}{instance}")),
+        None => Ok(api_id.into()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidInstance(pub String);
+
+impl std::fmt::Display for InvalidInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid instance name \"{}\": use letters, digits, '-' or '_'.",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidInstance {}
+
+pub fn api_id(target: &str) -> &str {
+    match target.split_once(INSTANCE_SEPARATOR) {
+        Some((api_id, _)) => api_id,
+        None => target,
+    }
+}
+
+pub fn is_valid_instance(instance: &str) -> bool {
+    !instance.is_empty()
+        && instance
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    pub api_specifier: ApiSpecifier,
+    pub schema: ApiSchema,
+    #[serde(default)]
+    pub replay: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Handshake {
-    ServerIdentification,
-    ClientIdentification(ApiSpecifier),
-    Web,
+    ServerIdentification(ServerIdentification),
+    ClientIdentification {
+        apis: Vec<Registration>,
+        proof: Option<String>,
+        #[serde(default)]
+        protocol_version: u32,
+        #[serde(default)]
+        identity: Option<String>,
+        #[serde(default)]
+        codecs: Vec<Codec>,
+    },
+    Accepted,
+    Negotiated {
+        codec: Codec,
+    },
+    Rejected(String),
+    // Shaped by ws_protocol_version so the web dashboard, which can't use this crate, sends
+    // the same thing.
+    Web(ObserverIdentification),
 }
 
 impl TryFrom<tungstenite::Message> for Handshake {
@@ -37,6 +129,33 @@ impl From<Handshake> for tungstenite::Message {
 }
 
 mod message;
+pub use message::Envelope;
+
+mod codec;
+pub use codec::Codec;
+
+mod msgpack;
+
+mod control;
+pub use control::DeliveryStatus;
+
+mod schema;
+pub use schema::{ApiSchema, PROTOCOL_VERSION};
+
+#[cfg(feature = "server")]
+mod auth;
+
+#[cfg(any(feature = "client", feature = "server"))]
+mod heartbeat;
+
+#[cfg(any(feature = "client", feature = "server"))]
+pub use heartbeat::Heartbeat;
+
+#[cfg(any(feature = "client", feature = "server"))]
+mod transport;
+
+#[cfg(any(feature = "client", feature = "server"))]
+pub use transport::*;
 
 #[cfg(feature = "client")]
 mod client;
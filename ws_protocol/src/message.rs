@@ -1,14 +1,98 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{msgpack, ApiSpecifier};
 
-use crate::ApiSpecifier;
+// Marks MessagePack frames whose content is carried as-is rather than as a JSON value.
+const TEXT_CONTENT: &str = "text_content";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub target: String,
     pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    // Milliseconds since the Unix epoch, taken where the message was created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    // Stamped by the server, whatever the sending client claims.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<u64>,
+    // Milliseconds since the Unix epoch, after which the sender no longer wants it delivered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    // Set on messages the server replays from its history rather than routes live.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replayed: bool,
 }
 
 impl Message {
+    pub fn new(target: String, content: String) -> Message {
+        Message {
+            target,
+            content,
+            correlation_id: None,
+            delivery_id: None,
+            id: Some(rand::random()),
+            timestamp: Some(now()),
+            sender: None,
+            trace_id: None,
+            expires_at: None,
+            replayed: false,
+        }
+    }
+
+    // The content is nested as a value rather than carried as an escaped JSON string. Content
+    // that isn't JSON is left as a string and flagged, so it arrives unchanged.
+    #[cfg(any(feature = "client", feature = "server"))]
+    pub(crate) fn to_msgpack(&self) -> Vec<u8> {
+        let mut value = serde_json::to_value(self).unwrap();
+        match serde_json::from_str(&self.content) {
+            Ok(content) => value["content"] = content,
+            Err(_) => value[TEXT_CONTENT] = true.into(),
+        }
+        msgpack::encode(&value)
+    }
+
+    fn from_msgpack(bytes: &[u8]) -> Result<Message, String> {
+        let mut value = msgpack::decode(bytes)?;
+        let text_content = match value.as_object_mut() {
+            Some(fields) => fields.remove(TEXT_CONTENT) == Some(Value::Bool(true)),
+            None => false,
+        };
+        if let Some(content) = value.get_mut("content") {
+            if !text_content {
+                *content = Value::String(content.to_string());
+            }
+        }
+
+        serde_json::from_value(value).map_err(|e| format!("Error parsing message: {e}"))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= now())
+            .unwrap_or(false)
+    }
+
+    pub fn envelope(&self) -> Envelope {
+        Envelope {
+            id: self.id.unwrap_or_default(),
+            timestamp: UNIX_EPOCH + Duration::from_millis(self.timestamp.unwrap_or_default()),
+            sender: self.sender.clone(),
+            trace_id: self.trace_id,
+            replayed: self.replayed,
+        }
+    }
+
     pub fn target_api_specifier(&self) -> ApiSpecifier {
         ApiSpecifier::Handles(self.target.clone())
     }
@@ -18,6 +102,54 @@ impl Message {
     }
 }
 
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub id: u64,
+    pub timestamp: SystemTime,
+    pub sender: Option<String>,
+    pub trace_id: Option<u64>,
+    pub replayed: bool,
+}
+
+impl Envelope {
+    // The trace this message belongs to: the one it was sent in, or a new one it starts.
+    pub fn trace(&self) -> u64 {
+        self.trace_id.unwrap_or(self.id)
+    }
+}
+
+// Just enough of a message to acknowledge it. Everything else, the content included, is
+// skipped over rather than copied out.
+#[cfg(feature = "server")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct Header {
+    pub target: String,
+    #[serde(default)]
+    pub delivery_id: Option<u64>,
+}
+
+#[cfg(feature = "server")]
+impl TryFrom<&tungstenite::Message> for Header {
+    type Error = String;
+
+    fn try_from(msg: &tungstenite::Message) -> Result<Self, Self::Error> {
+        match msg {
+            tungstenite::Message::Text(json) => {
+                serde_json::from_str(json).map_err(|e| format!("Error parsing message: {e}"))
+            }
+            tungstenite::Message::Binary(bytes) => msgpack::decode_as(bytes),
+            msg => Err(format!("Cannot parse message from {msg}")),
+        }
+    }
+}
+
 impl TryFrom<tungstenite::Message> for Message {
     type Error = String;
 
@@ -27,6 +159,7 @@ impl TryFrom<tungstenite::Message> for Message {
                 Ok(msg) => Ok(msg),
                 Err(e) => Err(format!("Error parsing message: {e}\nMessage:\n{json}")),
             },
+            tungstenite::Message::Binary(bytes) => Message::from_msgpack(&bytes),
             msg => Err(format!("Cannot parse message from {msg}")),
         }
     }
@@ -37,3 +170,36 @@ impl From<Message> for tungstenite::Message {
         tungstenite::Message::Text(serde_json::to_string(&msg).unwrap())
     }
 }
+
+#[cfg(all(test, any(feature = "client", feature = "server")))]
+mod tests {
+    use super::Message;
+
+    #[test]
+    fn carries_content_through_msgpack_unchanged() {
+        for content in [r#"{"Say":"hello"}"#, "not json", "\"a string\"", ""] {
+            let message = Message::new("echo".into(), content.into());
+            let decoded = Message::from_msgpack(&message.to_msgpack()).unwrap();
+            assert_eq!(decoded.content, content);
+        }
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn reads_a_header_from_either_codec() {
+        use super::Header;
+
+        let mut message = Message::new("echo@one".into(), r#"{"Say":"hello"}"#.into());
+        message.delivery_id = Some(7);
+        for frame in [
+            message.clone().into(),
+            tungstenite::Message::Binary(message.to_msgpack()),
+        ] {
+            let header = Header::try_from(&frame).unwrap();
+            assert_eq!(header.target, "echo@one");
+            assert_eq!(header.delivery_id, Some(7));
+        }
+
+        assert!(Header::try_from(&tungstenite::Message::Text("{}".into())).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+// The codec works on serde_json::Value trees, so the server can transcode between peers
+// without knowing their API types.
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+// The same nesting limit serde_json enforces, so hostile input can't exhaust the stack.
+const MAX_DEPTH: usize = 128;
+
+#[cfg(any(feature = "client", feature = "server"))]
+pub fn encode(value: &Value) -> Vec<u8> {
+    rmp_serde::to_vec(value).unwrap()
+}
+
+// Anything a JSON value can't hold, like binary data or a map with non-string keys, is an
+// error rather than being converted.
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    decode_as(bytes)
+}
+
+pub fn decode_as<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    let mut deserializer = rmp_serde::Deserializer::new(bytes);
+    // rmp-serde counts the outermost value as well as the ones nested in it.
+    deserializer.set_max_depth(MAX_DEPTH + 1);
+    let value = T::deserialize(&mut deserializer).map_err(|e| e.to_string())?;
+
+    match deserializer.get_ref().len() {
+        0 => Ok(value),
+        trailing => Err(format!("{trailing} trailing bytes")),
+    }
+}
+
+#[cfg(all(test, any(feature = "client", feature = "server")))]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{decode, encode, MAX_DEPTH};
+
+    #[test]
+    fn round_trips_json_values() {
+        let value = json!({
+            "target": "echo",
+            "content": {"Say": ["hello", 300, -1, 1.5, u64::MAX, i64::MIN, null, true]},
+        });
+
+        assert_eq!(decode(&encode(&value)).unwrap(), value);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let bytes = encode(&json!({"target": "echo", "content": [1, 300, "text"]}));
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "decoded {len} bytes");
+        }
+
+        // A length that runs past the end mustn't be trusted.
+        assert!(decode(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode(&[0xdb, 0xff, 0xff, 0xff, 0xff, b'a']).is_err());
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        assert!(decode(&[0xc0, 0xc0]).is_err());
+    }
+
+    #[test]
+    fn rejects_keys_that_are_not_strings() {
+        // {1: "one"}
+        assert!(decode(&[0x81, 0x01, 0xa3, b'o', b'n', b'e']).is_err());
+        // {"one": 1}
+        assert_eq!(
+            decode(&[0x81, 0xa3, b'o', b'n', b'e', 0x01]).unwrap(),
+            json!({"one": 1})
+        );
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| {
+            let mut bytes = vec![0x91; depth];
+            bytes.push(0xc0);
+            bytes
+        };
+
+        assert!(decode(&nested(MAX_DEPTH)).is_ok());
+        assert!(decode(&nested(MAX_DEPTH + 1)).is_err());
+    }
+
+    #[test]
+    fn decodes_nan_as_null() {
+        let mut float64 = vec![0xcb];
+        float64.extend_from_slice(&f64::NAN.to_be_bytes());
+        assert_eq!(decode(&float64).unwrap(), Value::Null);
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "client")]
+use serde::{
+    de::{self, Visitor},
+    forward_to_deserialize_any, Deserializer,
+};
+
+pub use ws_protocol_version::PROTOCOL_VERSION;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiSchema {
+    pub version: u32,
+    pub variants: Vec<String>,
+}
+
+impl ApiSchema {
+    pub fn understands(&self, variant: &str) -> bool {
+        self.variants.iter().any(|v| v == variant)
+    }
+}
+
+#[cfg(feature = "client")]
+pub fn variant_names<T>() -> Vec<String>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let mut variants = vec![];
+    T::deserialize(VariantNames(&mut variants)).ok();
+
+    variants.into_iter().map(String::from).collect()
+}
+
+#[cfg(any(feature = "client", feature = "server"))]
+pub fn variant_name<T>(value: &T) -> Option<String>
+where
+    T: Serialize,
+{
+    match serde_json::to_value(value).ok()? {
+        serde_json::Value::String(variant) => Some(variant),
+        serde_json::Value::Object(map) if map.len() == 1 => map.keys().next().cloned(),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "client")]
+struct VariantNames<'a>(&'a mut Vec<&'static str>);
+
+#[cfg(feature = "client")]
+impl<'de, 'a> Deserializer<'de> for VariantNames<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(de::Error::custom("not an enum"))
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.extend_from_slice(variants);
+        Err(de::Error::custom("variant names collected"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
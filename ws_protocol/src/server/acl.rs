@@ -0,0 +1,118 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::{api_id, ApiSpecifier};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Acl {
+    pub identities: HashMap<String, AclEntry>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AclEntry {
+    pub secret: String,
+    #[serde(default)]
+    pub emits: Vec<String>,
+    #[serde(default)]
+    pub handles: Vec<String>,
+}
+
+impl Acl {
+    pub fn from_env() -> Option<Acl> {
+        let path = std::env::var("BJORN_WS_ACL").ok()?;
+
+        Some(Acl::load(&path).unwrap_or_else(|e| panic!("Error loading ACL from {path}: {e}")))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Acl, String> {
+        let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Acl::parse(&json)
+    }
+
+    fn parse(json: &str) -> Result<Acl, String> {
+        let acl: Acl = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        // An identity's secret is all that stops another client from claiming it.
+        match acl
+            .identities
+            .iter()
+            .find(|(_, entry)| entry.secret.is_empty())
+        {
+            Some((identity, _)) => Err(format!("{identity} has no secret")),
+            None => Ok(acl),
+        }
+    }
+
+    pub fn entry(&self, identity: &str) -> Option<&AclEntry> {
+        self.identities.get(identity)
+    }
+}
+
+impl AclEntry {
+    pub fn allows(&self, api_specifier: &ApiSpecifier) -> bool {
+        let patterns = match api_specifier {
+            ApiSpecifier::Emits(_) => &self.emits,
+            ApiSpecifier::Handles(_) => &self.handles,
+        };
+        let target = api_specifier.target();
+
+        patterns
+            .iter()
+            .any(|pattern| pattern == "*" || pattern == target || pattern == api_id(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Acl, AclEntry};
+    use crate::ApiSpecifier;
+
+    fn entry(emits: &[&str], handles: &[&str]) -> AclEntry {
+        AclEntry {
+            secret: "secret".into(),
+            emits: emits.iter().map(|pattern| pattern.to_string()).collect(),
+            handles: handles.iter().map(|pattern| pattern.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn allows_listed_targets_for_their_role_only() {
+        let entry = entry(&["minecraft@survival"], &["discord"]);
+
+        assert!(entry.allows(&ApiSpecifier::Emits("minecraft@survival".into())));
+        assert!(entry.allows(&ApiSpecifier::Handles("discord".into())));
+
+        assert!(!entry.allows(&ApiSpecifier::Handles("minecraft@survival".into())));
+        assert!(!entry.allows(&ApiSpecifier::Emits("discord".into())));
+        assert!(!entry.allows(&ApiSpecifier::Emits("minecraft@creative".into())));
+        assert!(!entry.allows(&ApiSpecifier::Emits("minecraft".into())));
+    }
+
+    #[test]
+    fn allows_every_instance_of_a_listed_api() {
+        let entry = entry(&["minecraft"], &[]);
+
+        assert!(entry.allows(&ApiSpecifier::Emits("minecraft".into())));
+        assert!(entry.allows(&ApiSpecifier::Emits("minecraft@survival".into())));
+        assert!(!entry.allows(&ApiSpecifier::Emits("valheim".into())));
+    }
+
+    #[test]
+    fn allows_anything_for_a_wildcard() {
+        let entry = entry(&["*"], &[]);
+
+        assert!(entry.allows(&ApiSpecifier::Emits("valheim@main".into())));
+        assert!(!entry.allows(&ApiSpecifier::Handles("valheim@main".into())));
+    }
+
+    #[test]
+    fn requires_a_secret_for_every_identity() {
+        let acl = Acl::parse(r#"{"discord": {"secret": "hunter2", "emits": ["minecraft"]}}"#);
+        assert_eq!(acl.unwrap().entry("discord").unwrap().secret, "hunter2");
+
+        assert!(Acl::parse(r#"{"discord": {"emits": ["minecraft"]}}"#).is_err());
+        assert!(Acl::parse(r#"{"discord": {"secret": "", "emits": ["minecraft"]}}"#).is_err());
+    }
+}
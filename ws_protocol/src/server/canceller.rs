@@ -1,47 +1,44 @@
-use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tungstenite::protocol::frame::coding::CloseCode;
 
-use tokio::sync::{mpsc, oneshot};
-use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
-
-pub struct Canceller {
-    cancel: oneshot::Sender<()>,
-    senders: Arc<Mutex<Vec<mpsc::UnboundedSender<tungstenite::Message>>>>,
+pub(crate) struct Shutdown {
+    pub code: CloseCode,
+    pub reason: &'static str,
+    pub drain: bool,
 }
 
-impl Canceller {
-    pub fn new(
-        cancel: oneshot::Sender<()>,
-        mut sender_rx: mpsc::UnboundedReceiver<mpsc::UnboundedSender<tungstenite::Message>>,
-    ) -> Canceller {
-        let senders = Arc::new(Mutex::new(vec![]));
+pub struct Canceller(oneshot::Sender<Shutdown>);
 
-        {
-            let senders = senders.clone();
-            tokio::spawn(async move {
-                loop {
-                    let sender = match sender_rx.recv().await {
-                        Some(sender) => sender,
-                        None => break,
-                    };
+impl Canceller {
+    pub(crate) fn new(cancel: oneshot::Sender<Shutdown>) -> Canceller {
+        Canceller(cancel)
+    }
 
-                    senders.lock().unwrap().push(sender);
-                }
-            });
-        }
+    pub fn cancel(self) {
+        self.stop(Shutdown {
+            code: CloseCode::Error,
+            reason: "WS Server received SIGINT",
+            drain: false,
+        });
+    }
 
-        Canceller { cancel, senders }
+    pub fn shutdown(self) {
+        self.stop(Shutdown {
+            code: CloseCode::Normal,
+            reason: "WS Server shutting down",
+            drain: true,
+        });
     }
 
-    pub fn cancel(self) {
-        for sender in self.senders.lock().unwrap().iter() {
-            sender
-                .send(tungstenite::Message::Close(Some(CloseFrame {
-                    code: CloseCode::Error,
-                    reason: "WS Server received SIGINT".into(),
-                })))
-                .unwrap();
-        }
+    pub fn restart(self) {
+        self.stop(Shutdown {
+            code: CloseCode::Restart,
+            reason: "WS Server restarting",
+            drain: true,
+        });
+    }
 
-        self.cancel.send(()).unwrap();
+    fn stop(self, shutdown: Shutdown) {
+        self.0.send(shutdown).unwrap_or_default();
     }
 }
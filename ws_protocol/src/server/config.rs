@@ -0,0 +1,287 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use crate::{is_valid_instance, Heartbeat, INSTANCE_SEPARATOR};
+
+use super::Acl;
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    pub secret: Option<String>,
+    pub observer_secret: Option<String>,
+    // Lets observers in without a proof when there's no observer secret. They can only
+    // watch, but they see every message they subscribe to.
+    pub anonymous_observers: bool,
+    pub tls: Option<ServerTlsConfig>,
+    pub heartbeat: Heartbeat,
+    pub store: Option<StoreConfig>,
+    pub history: HashMap<String, HistoryPolicy>,
+    pub acl: Option<Acl>,
+    pub limits: Limits,
+    pub queue: QueueLimits,
+    pub shutdown_timeout: Option<Duration>,
+    pub unix_socket_mode: Option<u32>,
+    // Where to serve metrics for Prometheus to scrape, at /metrics.
+    pub metrics_address: Option<String>,
+}
+
+impl ServerConfig {
+    pub fn from_env() -> ServerConfig {
+        ServerConfig {
+            secret: std::env::var("BJORN_WS_SECRET").ok(),
+            observer_secret: std::env::var("BJORN_WS_OBSERVER_SECRET").ok(),
+            anonymous_observers: std::env::var("BJORN_WS_ANONYMOUS_OBSERVERS").is_ok(),
+            tls: ServerTlsConfig::from_env(),
+            heartbeat: Heartbeat::from_env(),
+            store: StoreConfig::from_env(),
+            history: std::env::var("BJORN_WS_HISTORY")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|entry| {
+                    let (target, policy) = entry.trim().split_once('=')?;
+                    Some((target.to_string(), HistoryPolicy::parse(policy)?))
+                })
+                .collect(),
+            acl: Acl::from_env(),
+            limits: Limits::from_env(),
+            queue: QueueLimits::from_env(),
+            shutdown_timeout: std::env::var("BJORN_WS_SHUTDOWN_TIMEOUT")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .map(Duration::from_secs),
+            unix_socket_mode: std::env::var("BJORN_WS_UNIX_SOCKET_MODE")
+                .ok()
+                .and_then(|mode| u32::from_str_radix(&mode, 8).ok()),
+            metrics_address: std::env::var("BJORN_WS_METRICS_ADDRESS").ok(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerTlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+impl ServerTlsConfig {
+    pub fn from_env() -> Option<ServerTlsConfig> {
+        match (
+            std::env::var("BJORN_WS_TLS_CERT"),
+            std::env::var("BJORN_WS_TLS_KEY"),
+        ) {
+            (Ok(cert_path), Ok(key_path)) => Some(ServerTlsConfig {
+                cert_path,
+                key_path,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StoreConfig {
+    pub dir: PathBuf,
+    pub retention: HashMap<String, Retention>,
+}
+
+impl StoreConfig {
+    pub fn from_env() -> Option<StoreConfig> {
+        let dir = std::env::var("BJORN_WS_STORE_DIR").ok()?;
+        let retention = std::env::var("BJORN_WS_STORE_RETENTION")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (target, retention) = entry.trim().split_once('=')?;
+                Some((target.to_string(), Retention::parse(retention)?))
+            })
+            .collect();
+
+        Some(StoreConfig {
+            dir: dir.into(),
+            retention,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub max_messages: usize,
+    pub max_age: Duration,
+}
+
+impl Retention {
+    pub fn parse(value: &str) -> Option<Retention> {
+        let (max_messages, max_age) = value.split_once(':')?;
+
+        Some(Retention {
+            max_messages: max_messages.parse().ok()?,
+            max_age: Duration::from_secs(max_age.parse().ok()?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryPolicy {
+    Last(usize),
+    // The latest message of each variant, e.g. the last StartupComplete and the last
+    // ShutdownComplete, in the order they were sent.
+    LatestPerKey,
+}
+
+impl HistoryPolicy {
+    pub fn parse(value: &str) -> Option<HistoryPolicy> {
+        match value {
+            "latest" => Some(HistoryPolicy::LatestPerKey),
+            max_messages => max_messages.parse().ok().map(HistoryPolicy::Last),
+        }
+    }
+}
+
+// Settings for an API apply to all of its instances, unless the instance has its own.
+pub(crate) fn configured_for<'a, T>(
+    settings: &'a HashMap<String, T>,
+    target: &str,
+) -> Option<&'a T> {
+    if let Some(setting) = settings.get(target) {
+        return Some(setting);
+    }
+
+    let (api_id, instance) = target.split_once(INSTANCE_SEPARATOR)?;
+    match is_valid_instance(instance) {
+        true => settings.get(api_id),
+        false => None,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    // With LimitPolicy::Disconnect, tungstenite stops reading at the limit. Dropping a
+    // message instead means reading it, to tell its sender why it went nowhere, so that
+    // policy lets a little more through before disconnecting.
+    pub max_message_size: Option<usize>,
+    pub connection_rate: Option<Rate>,
+    pub target_rates: HashMap<String, Rate>,
+    pub policy: LimitPolicy,
+}
+
+impl Limits {
+    pub fn from_env() -> Limits {
+        Limits {
+            max_message_size: std::env::var("BJORN_WS_MAX_MESSAGE_SIZE")
+                .ok()
+                .and_then(|size| size.parse().ok()),
+            connection_rate: std::env::var("BJORN_WS_RATE_LIMIT")
+                .ok()
+                .and_then(|rate| Rate::parse(&rate)),
+            target_rates: std::env::var("BJORN_WS_TARGET_RATE_LIMITS")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|entry| {
+                    let (target, rate) = entry.trim().split_once('=')?;
+                    Some((target.to_string(), Rate::parse(rate)?))
+                })
+                .collect(),
+            policy: std::env::var("BJORN_WS_LIMIT_POLICY")
+                .ok()
+                .and_then(|policy| LimitPolicy::parse(&policy))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl Rate {
+    pub fn parse(value: &str) -> Option<Rate> {
+        let (burst, per_second) = value.split_once(':')?;
+
+        Some(Rate {
+            burst: burst.parse().ok()?,
+            per_second: per_second.parse().ok()?,
+        })
+    }
+}
+
+// How many frames may wait to be written to a connection, and what to do with messages for
+// a peer that falls that far behind.
+#[derive(Debug, Clone)]
+pub struct QueueLimits {
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+    pub target_policies: HashMap<String, OverflowPolicy>,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        QueueLimits {
+            capacity: 1024,
+            policy: OverflowPolicy::default(),
+            target_policies: HashMap::new(),
+        }
+    }
+}
+
+impl QueueLimits {
+    pub fn from_env() -> QueueLimits {
+        let default = QueueLimits::default();
+
+        QueueLimits {
+            capacity: std::env::var("BJORN_WS_QUEUE_CAPACITY")
+                .ok()
+                .and_then(|capacity| capacity.parse().ok())
+                .filter(|capacity| *capacity > 0)
+                .unwrap_or(default.capacity),
+            policy: std::env::var("BJORN_WS_OVERFLOW_POLICY")
+                .ok()
+                .and_then(|policy| OverflowPolicy::parse(&policy))
+                .unwrap_or(default.policy),
+            target_policies: std::env::var("BJORN_WS_TARGET_OVERFLOW_POLICIES")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|entry| {
+                    let (target, policy) = entry.trim().split_once('=')?;
+                    Some((target.to_string(), OverflowPolicy::parse(policy)?))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    #[default]
+    DropOldest,
+    DropNewest,
+    Disconnect,
+}
+
+impl OverflowPolicy {
+    pub fn parse(value: &str) -> Option<OverflowPolicy> {
+        match value {
+            "drop-oldest" => Some(OverflowPolicy::DropOldest),
+            "drop-newest" => Some(OverflowPolicy::DropNewest),
+            "disconnect" => Some(OverflowPolicy::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LimitPolicy {
+    #[default]
+    Drop,
+    Disconnect,
+}
+
+impl LimitPolicy {
+    pub fn parse(value: &str) -> Option<LimitPolicy> {
+        match value {
+            "drop" => Some(LimitPolicy::Drop),
+            "disconnect" => Some(LimitPolicy::Disconnect),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use crate::{message::Message, schema};
+
+use super::{configured_for, HistoryPolicy};
+
+// Recent messages for selected targets, kept in memory so handlers that (re)connect can
+// catch up on what they missed, like retained messages in MQTT.
+pub struct History {
+    policies: HashMap<String, HistoryPolicy>,
+    messages: Mutex<HashMap<String, VecDeque<Message>>>,
+}
+
+impl History {
+    pub fn new(policies: HashMap<String, HistoryPolicy>) -> History {
+        History {
+            policies,
+            messages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, message: &Message) {
+        // Requests only make sense to the emitter waiting for the reply.
+        let policy = match configured_for(&self.policies, &message.target) {
+            Some(HistoryPolicy::Last(0)) | None => return,
+            Some(policy) if message.correlation_id.is_none() => *policy,
+            _ => return,
+        };
+
+        let mut messages = self.messages.lock().unwrap();
+        let history = messages.entry(message.target.clone()).or_default();
+
+        match policy {
+            HistoryPolicy::Last(max_messages) => {
+                while history.len() >= max_messages {
+                    history.pop_front();
+                }
+            }
+            HistoryPolicy::LatestPerKey => {
+                let variant = key(message);
+                history.retain(|recorded| key(recorded) != variant);
+            }
+        }
+
+        history.push_back(Message {
+            delivery_id: None,
+            ..message.clone()
+        });
+    }
+
+    pub fn replay(&self, target: &str) -> Vec<Message> {
+        self.messages
+            .lock()
+            .unwrap()
+            .get(target)
+            .map(|history| {
+                // Like stored messages, they're no use to the sender once its TTL has passed.
+                history
+                    .iter()
+                    .filter(|message| !message.is_expired())
+                    .map(|message| Message {
+                        replayed: true,
+                        ..message.clone()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Messages are externally tagged enums, so the variant name is the key.
+fn key(message: &Message) -> Option<String> {
+    let content: serde_json::Value = serde_json::from_str(&message.content).ok()?;
+    schema::variant_name(&content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::History;
+    use crate::{message::Message, server::HistoryPolicy};
+
+    fn history() -> History {
+        History::new(HashMap::from([(
+            "echo".to_string(),
+            HistoryPolicy::Last(10),
+        )]))
+    }
+
+    fn replay(history: &History) -> Vec<String> {
+        history
+            .replay("echo")
+            .into_iter()
+            .map(|message| message.content)
+            .collect()
+    }
+
+    #[test]
+    fn skips_messages_past_their_ttl_when_replayed() {
+        let history = history();
+        history.record(&Message {
+            expires_at: Some(crate::message::now() - 1),
+            ..Message::new("echo".into(), "stale".into())
+        });
+        history.record(&Message::new("echo".into(), "fresh".into()));
+
+        assert_eq!(replay(&history), ["fresh"]);
+    }
+
+    #[test]
+    fn keeps_the_latest_message_per_variant() {
+        let history = History::new(HashMap::from([(
+            "echo".to_string(),
+            HistoryPolicy::LatestPerKey,
+        )]));
+        for content in [r#"{"Say":"one"}"#, r#""Hush""#, r#"{"Say":"two"}"#] {
+            history.record(&Message::new("echo".into(), content.into()));
+        }
+
+        assert_eq!(replay(&history), [r#""Hush""#, r#"{"Say":"two"}"#]);
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crate::api_id;
+
+use super::{LimitPolicy, Limits, Rate};
+
+#[derive(Debug, Default)]
+pub struct LimitStats {
+    rate_limited: AtomicU64,
+    oversized: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitCounts {
+    pub rate_limited: u64,
+    pub oversized: u64,
+    pub disconnected: u64,
+}
+
+impl LimitStats {
+    pub fn snapshot(&self) -> LimitCounts {
+        LimitCounts {
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            oversized: self.oversized.load(Ordering::Relaxed),
+            disconnected: self.disconnected.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn count_oversized(&self) {
+        self.oversized.fetch_add(1, Ordering::Relaxed);
+        self.disconnected.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    refilled_at: Instant,
+    throttled: bool,
+}
+
+enum Throttled {
+    Started,
+    Continuing,
+}
+
+impl TokenBucket {
+    fn new(rate: Rate) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: rate.burst as f64,
+            refilled_at: Instant::now(),
+            throttled: false,
+        }
+    }
+
+    fn take(&mut self) -> Result<(), Throttled> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_second).min(self.rate.burst as f64);
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return match std::mem::replace(&mut self.throttled, true) {
+                true => Err(Throttled::Continuing),
+                false => Err(Throttled::Started),
+            };
+        }
+
+        self.tokens -= 1.0;
+        self.throttled = false;
+        Ok(())
+    }
+}
+
+pub(crate) enum Verdict {
+    Allow,
+    Drop,
+    Disconnect,
+}
+
+impl Verdict {
+    pub(crate) fn admits(self) -> Option<bool> {
+        match self {
+            Verdict::Allow => Some(true),
+            Verdict::Drop => Some(false),
+            Verdict::Disconnect => None,
+        }
+    }
+}
+
+pub(crate) struct Limiter {
+    limits: Limits,
+    stats: Arc<LimitStats>,
+    peer: String,
+    connection: Option<TokenBucket>,
+    targets: HashMap<String, TokenBucket>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limits: Limits, stats: Arc<LimitStats>, peer: String) -> Limiter {
+        Limiter {
+            connection: limits.connection_rate.map(TokenBucket::new),
+            limits,
+            stats,
+            peer,
+            targets: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn check_connection(&mut self) -> Verdict {
+        let taken = match &mut self.connection {
+            Some(bucket) => bucket.take(),
+            None => Ok(()),
+        };
+
+        self.verdict(taken, "its rate limit")
+    }
+
+    pub(crate) fn check_size(&mut self, size: usize) -> Verdict {
+        match self.limits.max_message_size {
+            Some(max_message_size) if size > max_message_size => {}
+            _ => return Verdict::Allow,
+        }
+
+        self.stats.oversized.fetch_add(1, Ordering::Relaxed);
+
+        match self.limits.policy {
+            LimitPolicy::Drop => {
+                println!(
+                    "{} sent an oversized message ({size} bytes), dropping it",
+                    self.peer
+                );
+
+                Verdict::Drop
+            }
+            LimitPolicy::Disconnect => {
+                println!(
+                    "{} sent an oversized message ({size} bytes), disconnecting",
+                    self.peer
+                );
+                self.stats.disconnected.fetch_add(1, Ordering::Relaxed);
+
+                Verdict::Disconnect
+            }
+        }
+    }
+
+    pub(crate) fn check_target(&mut self, target: &str) -> Verdict {
+        let rate = match self
+            .limits
+            .target_rates
+            .get(target)
+            .or_else(|| self.limits.target_rates.get(api_id(target)))
+        {
+            Some(rate) => *rate,
+            None => return Verdict::Allow,
+        };
+
+        let taken = self
+            .targets
+            .entry(target.to_string())
+            .or_insert_with(|| TokenBucket::new(rate))
+            .take();
+
+        self.verdict(taken, &format!("its {target} rate limit"))
+    }
+
+    fn verdict(&self, taken: Result<(), Throttled>, limit: &str) -> Verdict {
+        let throttled = match taken {
+            Ok(()) => return Verdict::Allow,
+            Err(throttled) => throttled,
+        };
+
+        self.stats.rate_limited.fetch_add(1, Ordering::Relaxed);
+
+        match self.limits.policy {
+            LimitPolicy::Drop => {
+                if let Throttled::Started = throttled {
+                    println!("{} exceeded {limit}, dropping messages", self.peer);
+                }
+
+                Verdict::Drop
+            }
+            LimitPolicy::Disconnect => {
+                println!("{} exceeded {limit}, disconnecting", self.peer);
+                self.stats.disconnected.fetch_add(1, Ordering::Relaxed);
+
+                Verdict::Disconnect
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Rate, Throttled, TokenBucket};
+
+    fn bucket() -> TokenBucket {
+        TokenBucket::new(Rate {
+            burst: 2,
+            per_second: 1.0,
+        })
+    }
+
+    #[test]
+    fn allows_a_burst_then_throttles() {
+        let mut bucket = bucket();
+
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_ok());
+        assert!(matches!(bucket.take(), Err(Throttled::Started)));
+        assert!(matches!(bucket.take(), Err(Throttled::Continuing)));
+    }
+
+    #[test]
+    fn refills_at_the_rate_up_to_the_burst() {
+        let mut bucket = bucket();
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_err());
+
+        bucket.refilled_at -= Duration::from_secs(1);
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_err());
+
+        bucket.refilled_at -= Duration::from_secs(60);
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_ok());
+        assert!(matches!(bucket.take(), Err(Throttled::Started)));
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use futures_util::future;
+use hyper::{
+    header::{ALLOW, CONTENT_TYPE},
+    server::conn::Http,
+    service::service_fn,
+    Body, Method, Request, Response, StatusCode,
+};
+use tokio::net::TcpListener;
+
+use crate::ApiSpecifier;
+
+use super::{LimitStats, QueueStats};
+
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Targets are whatever clients make them, so only this many get a series of their own in each
+// family, and any more are counted together under OTHER_TARGETS.
+const MAX_TARGETS: usize = 256;
+const OTHER_TARGETS: &str = "other";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HandshakeFailure {
+    WebSocket,
+    Tls,
+    Io,
+    Invalid,
+    Unauthorized,
+    Version,
+    Registration,
+}
+
+impl HandshakeFailure {
+    const ALL: [HandshakeFailure; 7] = [
+        HandshakeFailure::WebSocket,
+        HandshakeFailure::Tls,
+        HandshakeFailure::Io,
+        HandshakeFailure::Invalid,
+        HandshakeFailure::Unauthorized,
+        HandshakeFailure::Version,
+        HandshakeFailure::Registration,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            HandshakeFailure::WebSocket => "websocket",
+            HandshakeFailure::Tls => "tls",
+            HandshakeFailure::Io => "io",
+            HandshakeFailure::Invalid => "invalid",
+            HandshakeFailure::Unauthorized => "unauthorized",
+            HandshakeFailure::Version => "version",
+            HandshakeFailure::Registration => "registration",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Dropped {
+    RateLimited,
+    QueueOverflow,
+    NoRoute,
+    Forbidden,
+}
+
+impl Dropped {
+    fn label(self) -> &'static str {
+        match self {
+            Dropped::RateLimited => "rate_limited",
+            Dropped::QueueOverflow => "queue_overflow",
+            Dropped::NoRoute => "no_route",
+            Dropped::Forbidden => "forbidden",
+        }
+    }
+}
+
+// Counters looked up on every message, so adding to one that exists only takes a read lock.
+#[derive(Debug, Default)]
+struct Counters(RwLock<HashMap<String, AtomicU64>>);
+
+impl Counters {
+    fn add(&self, key: &str) {
+        if let Some(counter) = self.0.read().unwrap().get(key) {
+            counter.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let mut counters = self.0.write().unwrap();
+        let key = match counters.contains_key(key) || counters.len() < MAX_TARGETS {
+            true => key,
+            false => OTHER_TARGETS,
+        };
+        counters
+            .entry(key.into())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Vec<(String, u64)> {
+        let mut counts = self
+            .0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, counter)| (key.clone(), counter.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>();
+        counts.sort();
+        counts
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ClientCounts {
+    connected: i64,
+    disconnects: u64,
+    reconnects: u64,
+}
+
+// Everything ws_server can report about itself, rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub(crate) limit_stats: Arc<LimitStats>,
+    pub(crate) queue_stats: Arc<QueueStats>,
+    clients: Mutex<HashMap<ApiSpecifier, ClientCounts>>,
+    observers: AtomicI64,
+    routed: Counters,
+    rate_limited: Counters,
+    overflowed: Counters,
+    unrouted: Counters,
+    forbidden: Counters,
+    handshake_failures: [AtomicU64; HandshakeFailure::ALL.len()],
+}
+
+impl Metrics {
+    pub(crate) fn count_registered(&self, api_specifier: &ApiSpecifier) {
+        let mut clients = self.clients.lock().unwrap();
+        let counts = clients.entry(api_specifier.clone()).or_default();
+
+        // A registration after one of the API's clients went away is taken to be it coming
+        // back, whether or not it's from the same address.
+        if counts.reconnects < counts.disconnects {
+            counts.reconnects += 1;
+        }
+        counts.connected += 1;
+    }
+
+    pub(crate) fn count_unregistered(&self, api_specifier: &ApiSpecifier) {
+        let mut clients = self.clients.lock().unwrap();
+        let counts = clients.entry(api_specifier.clone()).or_default();
+
+        counts.connected -= 1;
+        counts.disconnects += 1;
+    }
+
+    pub(crate) fn count_observer(&self, connected: bool) {
+        let change = if connected { 1 } else { -1 };
+        self.observers.fetch_add(change, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_routed(&self, target: &str) {
+        self.routed.add(target);
+    }
+
+    pub(crate) fn count_dropped(&self, target: &str, reason: Dropped) {
+        self.dropped(reason).add(target);
+    }
+
+    fn dropped(&self, reason: Dropped) -> &Counters {
+        match reason {
+            Dropped::RateLimited => &self.rate_limited,
+            Dropped::QueueOverflow => &self.overflowed,
+            Dropped::NoRoute => &self.unrouted,
+            Dropped::Forbidden => &self.forbidden,
+        }
+    }
+
+    pub(crate) fn count_handshake_failure(&self, failure: HandshakeFailure) {
+        let index = HandshakeFailure::ALL
+            .iter()
+            .position(|f| *f == failure)
+            .unwrap();
+        self.handshake_failures[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let mut clients = self
+            .clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(api_specifier, counts)| (api_labels(api_specifier), *counts))
+            .collect::<Vec<_>>();
+        clients.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        header(&mut out, "clients", "gauge", "Connected clients per API.");
+        samples(
+            &mut out,
+            "clients",
+            clients
+                .iter()
+                .map(|(labels, counts)| (labels.clone(), counts.connected)),
+        );
+
+        header(
+            &mut out,
+            "reconnects_total",
+            "counter",
+            "Registrations of an API after one of its clients disconnected.",
+        );
+        samples(
+            &mut out,
+            "reconnects_total",
+            clients
+                .iter()
+                .map(|(labels, counts)| (labels.clone(), counts.reconnects)),
+        );
+
+        header(&mut out, "observers", "gauge", "Connected observers.");
+        sample(
+            &mut out,
+            "observers",
+            "",
+            self.observers.load(Ordering::Relaxed),
+        );
+
+        header(
+            &mut out,
+            "messages_routed_total",
+            "counter",
+            "Messages received from clients and routed, per target.",
+        );
+        samples(
+            &mut out,
+            "messages_routed_total",
+            self.routed
+                .snapshot()
+                .into_iter()
+                .map(|(target, count)| (format!("target=\"{}\"", escape(&target)), count)),
+        );
+
+        header(
+            &mut out,
+            "messages_dropped_total",
+            "counter",
+            "Messages that weren't delivered, per target and reason.",
+        );
+        let dropped = [
+            Dropped::RateLimited,
+            Dropped::QueueOverflow,
+            Dropped::NoRoute,
+            Dropped::Forbidden,
+        ]
+        .into_iter()
+        .flat_map(|reason| {
+            self.dropped(reason)
+                .snapshot()
+                .into_iter()
+                .map(move |(target, count)| {
+                    let labels = format!(
+                        "target=\"{}\",reason=\"{}\"",
+                        escape(&target),
+                        reason.label()
+                    );
+                    (labels, count)
+                })
+        });
+        samples(&mut out, "messages_dropped_total", dropped);
+
+        header(
+            &mut out,
+            "handshake_failures_total",
+            "counter",
+            "Connections that failed to complete the handshake, per reason.",
+        );
+        for (failure, count) in HandshakeFailure::ALL.iter().zip(&self.handshake_failures) {
+            let labels = format!("reason=\"{}\"", failure.label());
+            let count = count.load(Ordering::Relaxed);
+            sample(&mut out, "handshake_failures_total", &labels, count);
+        }
+
+        let queue = self.queue_stats.snapshot();
+        header(
+            &mut out,
+            "queue_depth",
+            "gauge",
+            "Frames waiting to be written, across all connections.",
+        );
+        sample(&mut out, "queue_depth", "", queue.depth);
+        header(
+            &mut out,
+            "queue_peak_depth",
+            "gauge",
+            "The longest any single connection's queue has been.",
+        );
+        sample(&mut out, "queue_peak_depth", "", queue.peak_depth);
+        header(
+            &mut out,
+            "queue_disconnects_total",
+            "counter",
+            "Connections dropped for falling too far behind.",
+        );
+        sample(&mut out, "queue_disconnects_total", "", queue.disconnected);
+
+        let limits = self.limit_stats.snapshot();
+        header(
+            &mut out,
+            "rate_limited_total",
+            "counter",
+            "Messages over a rate limit.",
+        );
+        sample(&mut out, "rate_limited_total", "", limits.rate_limited);
+        header(
+            &mut out,
+            "oversized_total",
+            "counter",
+            "Messages over the size limit.",
+        );
+        sample(&mut out, "oversized_total", "", limits.oversized);
+        header(
+            &mut out,
+            "limit_disconnects_total",
+            "counter",
+            "Connections dropped for exceeding a limit.",
+        );
+        sample(&mut out, "limit_disconnects_total", "", limits.disconnected);
+
+        out
+    }
+}
+
+fn api_labels(api_specifier: &ApiSpecifier) -> String {
+    let role = match api_specifier {
+        ApiSpecifier::Emits(_) => "emits",
+        ApiSpecifier::Handles(_) => "handles",
+    };
+
+    format!(
+        "role=\"{role}\",target=\"{}\"",
+        escape(api_specifier.target())
+    )
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP bjorn_ws_{name} {help}").unwrap();
+    writeln!(out, "# TYPE bjorn_ws_{name} {kind}").unwrap();
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    match labels {
+        "" => writeln!(out, "bjorn_ws_{name} {value}").unwrap(),
+        labels => writeln!(out, "bjorn_ws_{name}{{{labels}}} {value}").unwrap(),
+    }
+}
+
+// A family without any series yet is left with just its HELP and TYPE lines. A stand-in zero
+// without labels would be a series of its own, sitting alongside the labelled ones later.
+fn samples<V: std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    samples: impl IntoIterator<Item = (String, V)>,
+) {
+    for (labels, value) in samples {
+        sample(out, name, &labels, value);
+    }
+}
+
+// Targets come from clients, so they may hold anything.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// One request per connection, which is all a scraper needs.
+pub(crate) async fn serve(addr: String, metrics: Arc<Metrics>) {
+    let listener = TcpListener::bind(&addr)
+        .await
+        .unwrap_or_else(|e| panic!("Error binding to {addr}: {e}"));
+    println!("Serving metrics on: {addr}");
+
+    let http = Http::new()
+        .http1_only(true)
+        .http1_keep_alive(false)
+        .max_buf_size(MAX_REQUEST_SIZE)
+        .clone();
+
+    while let Ok((stream, _)) = listener.accept().await {
+        let metrics = metrics.clone();
+        let service = service_fn(move |request| {
+            future::ready(Ok::<_, Infallible>(respond(request, &metrics)))
+        });
+        let connection = http.serve_connection(stream, service);
+        tokio::task::spawn(async move {
+            tokio::time::timeout(REQUEST_TIMEOUT, connection).await.ok();
+        });
+    }
+}
+
+fn respond(request: Request<Body>, metrics: &Metrics) -> Response<Body> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(metrics.render())),
+        (&Method::GET, _) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+        _ => Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(ALLOW, "GET")
+            .body(Body::empty()),
+    };
+
+    response.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dropped, Metrics, MAX_TARGETS};
+
+    #[test]
+    fn leaves_empty_families_without_samples() {
+        let rendered = Metrics::default().render();
+
+        assert!(rendered.contains("# TYPE bjorn_ws_messages_routed_total counter\n"));
+        assert!(!rendered.contains("\nbjorn_ws_messages_routed_total"));
+        assert!(!rendered.contains("\nbjorn_ws_clients"));
+    }
+
+    #[test]
+    fn counts_targets_past_the_limit_together() {
+        let metrics = Metrics::default();
+        for index in 0..MAX_TARGETS + 10 {
+            metrics.count_routed(&format!("target{index}"));
+            metrics.count_dropped(&format!("target{index}"), Dropped::NoRoute);
+        }
+        metrics.count_routed("target0");
+
+        let rendered = metrics.render();
+        let series = |name: &str| {
+            rendered
+                .lines()
+                .filter(|line| line.starts_with(&format!("bjorn_ws_{name}{{")))
+                .count()
+        };
+        assert_eq!(series("messages_routed_total"), MAX_TARGETS + 1);
+        assert_eq!(series("messages_dropped_total"), MAX_TARGETS + 1);
+        assert!(rendered.contains("bjorn_ws_messages_routed_total{target=\"target0\"} 2\n"));
+        assert!(rendered.contains("bjorn_ws_messages_routed_total{target=\"other\"} 10\n"));
+    }
+}
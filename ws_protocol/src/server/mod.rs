@@ -1,21 +1,40 @@
 mod runner;
 use runner::*;
 
+mod acl;
+pub use acl::*;
+
 mod canceller;
 use canceller::*;
 
-use tokio::sync::{mpsc, oneshot};
+mod config;
+pub use config::*;
+
+mod history;
+
+mod limits;
+pub use limits::{LimitCounts, LimitStats};
+
+mod metrics;
+pub use metrics::Metrics;
+
+mod queue;
+pub use queue::{QueueCounts, QueueStats};
+
+mod routing;
+
+mod store;
+
+mod tls;
+
+use tokio::sync::oneshot;
 
 pub struct WsServer;
 
 impl WsServer {
-    pub fn new() -> (Runner, Canceller) {
+    pub fn new(config: ServerConfig) -> (Runner, Canceller) {
         let (cancel, on_cancel) = oneshot::channel();
-        let (sender_tx, sender_rx) = mpsc::unbounded_channel();
 
-        (
-            Runner::new(on_cancel, sender_tx),
-            Canceller::new(cancel, sender_rx),
-        )
+        (Runner::new(config, on_cancel), Canceller::new(cancel))
     }
 }
@@ -0,0 +1,232 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::sync::Notify;
+
+use super::{
+    configured_for,
+    metrics::{Dropped, Metrics},
+    OverflowPolicy, ServerConfig,
+};
+
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    depth: AtomicU64,
+    peak_depth: AtomicU64,
+    dropped: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueCounts {
+    // Frames waiting to be written, across all connections.
+    pub depth: u64,
+    // The longest any single connection's queue has been.
+    pub peak_depth: u64,
+    pub dropped: u64,
+    pub disconnected: u64,
+}
+
+impl QueueStats {
+    pub fn snapshot(&self) -> QueueCounts {
+        QueueCounts {
+            depth: self.depth.load(Ordering::Relaxed),
+            peak_depth: self.peak_depth.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            disconnected: self.disconnected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Queued {
+    // None for frames queued with Tx::send, which are never dropped.
+    target: Option<String>,
+    frame: tungstenite::Message,
+}
+
+#[derive(Default)]
+struct State {
+    frames: VecDeque<Queued>,
+    // The peer fell too far behind and is being dropped.
+    overflowed: bool,
+    // The connection stopped writing.
+    finished: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    pushed: Notify,
+    overflowed: Notify,
+    finished: Notify,
+    config: Arc<ServerConfig>,
+    stats: Arc<QueueStats>,
+    metrics: Arc<Metrics>,
+    peer: String,
+}
+
+// The frames waiting to be written to one connection. Messages for a peer that can't keep
+// up are dropped or get it disconnected, according to the overflow policy for their target.
+pub(crate) fn channel(config: Arc<ServerConfig>, metrics: Arc<Metrics>, peer: String) -> (Tx, Rx) {
+    let shared = Arc::new(Shared {
+        state: Mutex::default(),
+        pushed: Notify::new(),
+        overflowed: Notify::new(),
+        finished: Notify::new(),
+        config,
+        stats: metrics.queue_stats.clone(),
+        metrics,
+        peer,
+    });
+
+    (Tx(shared.clone()), Rx(shared))
+}
+
+#[derive(Clone)]
+pub(crate) struct Tx(Arc<Shared>);
+
+impl Tx {
+    // For frames that are bounded some other way, and always get through: control frames
+    // sent once per registration, and the stored and replayed messages a handler is sent on
+    // registering, which are capped by their retention settings.
+    pub(crate) fn send(&self, frame: tungstenite::Message) {
+        let mut state = self.0.state.lock().unwrap();
+        if !state.overflowed && !state.finished {
+            self.push(&mut state, None, frame);
+        }
+    }
+
+    // Returns whether the message was queued.
+    pub(crate) fn send_message(&self, target: &str, frame: tungstenite::Message) -> bool {
+        let shared = &self.0;
+        let mut state = shared.state.lock().unwrap();
+        if state.overflowed || state.finished {
+            return false;
+        }
+
+        let limits = &shared.config.queue;
+        if state.frames.len() >= limits.capacity {
+            let policy = configured_for(&limits.target_policies, target)
+                .copied()
+                .unwrap_or(limits.policy);
+
+            // Without an older message for the same target to make room, the new one goes,
+            // so a busy target can't push out everything else.
+            let oldest = state
+                .frames
+                .iter()
+                .position(|queued| queued.target.as_deref() == Some(target));
+
+            match (policy, oldest) {
+                (OverflowPolicy::DropOldest, Some(oldest)) => {
+                    state.frames.remove(oldest);
+                    shared.stats.depth.fetch_sub(1, Ordering::Relaxed);
+                    self.count_dropped(target);
+                }
+                (OverflowPolicy::DropOldest, None) | (OverflowPolicy::DropNewest, _) => {
+                    self.count_dropped(target);
+                    return false;
+                }
+                (OverflowPolicy::Disconnect, _) => {
+                    println!("{} can't keep up, disconnecting", shared.peer);
+                    shared.stats.disconnected.fetch_add(1, Ordering::Relaxed);
+
+                    // There's no point queueing a Close frame for a peer that isn't reading.
+                    shared
+                        .stats
+                        .depth
+                        .fetch_sub(state.frames.len() as u64, Ordering::Relaxed);
+                    for queued in state.frames.drain(..) {
+                        match &queued.target {
+                            Some(target) => self.count_dropped(target),
+                            None => {
+                                shared.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    state.overflowed = true;
+                    self.count_dropped(target);
+                    shared.overflowed.notify_waiters();
+                    shared.pushed.notify_one();
+                    return false;
+                }
+            }
+        }
+
+        self.push(&mut state, Some(target.into()), frame);
+        true
+    }
+
+    fn count_dropped(&self, target: &str) {
+        self.0.stats.dropped.fetch_add(1, Ordering::Relaxed);
+        self.0.metrics.count_dropped(target, Dropped::QueueOverflow);
+    }
+
+    fn push(&self, state: &mut State, target: Option<String>, frame: tungstenite::Message) {
+        let shared = &self.0;
+        state.frames.push_back(Queued { target, frame });
+        shared.stats.depth.fetch_add(1, Ordering::Relaxed);
+        shared
+            .stats
+            .peak_depth
+            .fetch_max(state.frames.len() as u64, Ordering::Relaxed);
+        shared.pushed.notify_one();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.0.state.lock().unwrap().finished
+    }
+
+    // Resolves once the connection is done writing.
+    pub(crate) async fn closed(&self) {
+        let finished = self.0.finished.notified();
+        if !self.is_closed() {
+            finished.await;
+        }
+    }
+}
+
+pub(crate) struct Rx(Arc<Shared>);
+
+impl Rx {
+    pub(crate) async fn recv(&mut self) -> Option<tungstenite::Message> {
+        loop {
+            {
+                let mut state = self.0.state.lock().unwrap();
+                if let Some(queued) = state.frames.pop_front() {
+                    self.0.stats.depth.fetch_sub(1, Ordering::Relaxed);
+                    return Some(queued.frame);
+                }
+                if state.overflowed {
+                    return None;
+                }
+            }
+
+            self.0.pushed.notified().await;
+        }
+    }
+
+    // Resolves once the peer has fallen too far behind and should be dropped, even if it's
+    // in the middle of being written to.
+    pub(crate) async fn overflowed(&self) {
+        let overflowed = self.0.overflowed.notified();
+        if !self.0.state.lock().unwrap().overflowed {
+            overflowed.await;
+        }
+    }
+}
+
+impl Drop for Rx {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        let unsent = state.frames.len() as u64;
+        state.frames.clear();
+        state.finished = true;
+        self.0.stats.depth.fetch_sub(unsent, Ordering::Relaxed);
+        self.0.finished.notify_waiters();
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use arc_swap::{ArcSwap, Guard};
+
+use crate::{api_id, ApiSchema, Codec};
+
+use super::queue::Tx;
+
+pub(crate) type ConnectionId = u64;
+
+pub(crate) fn next_connection_id() -> ConnectionId {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Clone)]
+pub(crate) struct Peer {
+    pub(crate) id: ConnectionId,
+    pub(crate) tx: Tx,
+    pub(crate) schema: ApiSchema,
+    pub(crate) codec: Codec,
+}
+
+// The peers registered for each target on one side, emitters or handlers. Routing reads a
+// snapshot without locking; (dis)connects copy the target's peers and swap the table, since
+// they're rare next to messages.
+#[derive(Default)]
+pub(crate) struct RoutingTable {
+    peers: ArcSwap<HashMap<String, Arc<[Peer]>>>,
+    writer: Mutex<()>,
+}
+
+impl RoutingTable {
+    pub(crate) fn peers(&self, target: &str) -> Option<Arc<[Peer]>> {
+        self.peers
+            .load()
+            .get(target)
+            .filter(|peers| !peers.is_empty())
+            .cloned()
+    }
+
+    pub(crate) fn schemas(&self, target: &str) -> Vec<ApiSchema> {
+        self.peers(target)
+            .map(|peers| peers.iter().map(|peer| peer.schema.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    // Changes are made one at a time, and the table stays as whoever holds this sees it.
+    pub(crate) fn lock(&self) -> TableLock<'_> {
+        TableLock {
+            table: self,
+            _writer: self.writer.lock().unwrap(),
+        }
+    }
+}
+
+pub(crate) struct TableLock<'a> {
+    table: &'a RoutingTable,
+    _writer: MutexGuard<'a, ()>,
+}
+
+impl TableLock<'_> {
+    pub(crate) fn peers(&self, target: &str) -> Option<Arc<[Peer]>> {
+        self.table.peers(target)
+    }
+
+    pub(crate) fn insert(&self, target: &str, peer: Peer) {
+        self.update(target, |peers| {
+            peers.push(peer);
+        });
+    }
+
+    pub(crate) fn remove(&self, target: &str, id: ConnectionId) {
+        self.update(target, |peers| peers.retain(|peer| peer.id != id));
+    }
+
+    fn update<F: FnOnce(&mut Vec<Peer>)>(&self, target: &str, change: F) {
+        let mut table = HashMap::clone(&self.table.peers.load());
+        let mut peers = table
+            .get(target)
+            .map(|peers| peers.to_vec())
+            .unwrap_or_default();
+        change(&mut peers);
+
+        match peers.is_empty() {
+            true => table.remove(target),
+            false => table.insert(target.into(), peers.into()),
+        };
+        self.table.peers.store(Arc::new(table));
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Observer {
+    pub(crate) id: ConnectionId,
+    pub(crate) tx: Tx,
+    pub(crate) subscriptions: Vec<String>,
+}
+
+impl Observer {
+    pub(crate) fn observes(&self, target: &str) -> bool {
+        self.subscriptions.is_empty()
+            || self
+                .subscriptions
+                .iter()
+                .any(|s| s == target || s == api_id(target))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Observers {
+    observers: ArcSwap<Vec<Observer>>,
+}
+
+impl Observers {
+    pub(crate) fn snapshot(&self) -> Guard<Arc<Vec<Observer>>> {
+        self.observers.load()
+    }
+
+    pub(crate) fn insert(&self, observer: Observer) {
+        self.observers.rcu(|observers| {
+            let mut observers = Vec::clone(observers);
+            observers.push(observer.clone());
+            observers
+        });
+    }
+
+    pub(crate) fn remove(&self, id: ConnectionId) {
+        self.observers.rcu(|observers| {
+            let mut observers = Vec::clone(observers);
+            observers.retain(|observer| observer.id != id);
+            observers
+        });
+    }
+}
+
+const PRUNE_REQUESTS_AT: usize = 4096;
+const REQUEST_TTL: Duration = Duration::from_secs(60);
+
+// Which connection each pending request came from, so the reply goes back there alone.
+// Requests that are never answered are forgotten once there are enough of them to matter.
+#[derive(Default)]
+pub(crate) struct Requests(Mutex<HashMap<u64, (ConnectionId, Instant)>>);
+
+impl Requests {
+    pub(crate) fn insert(&self, correlation_id: u64, from: ConnectionId) {
+        let mut requests = self.0.lock().unwrap();
+        if requests.len() >= PRUNE_REQUESTS_AT {
+            requests.retain(|_, (_, sent_at)| sent_at.elapsed() < REQUEST_TTL);
+        }
+
+        requests.insert(correlation_id, (from, Instant::now()));
+    }
+
+    pub(crate) fn take(&self, correlation_id: u64) -> Option<ConnectionId> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&correlation_id)
+            .map(|(from, _)| from)
+    }
+
+    pub(crate) fn forget(&self, from: ConnectionId) {
+        self.0.lock().unwrap().retain(|_, (id, _)| *id != from);
+    }
+}
@@ -1,81 +1,378 @@
 use std::{
     collections::HashMap,
-    net::SocketAddr,
+    future::Future,
+    pin::Pin,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use futures_util::{stream::SplitStream, SinkExt, StreamExt, TryStreamExt};
+use futures_util::{
+    future,
+    stream::{SplitSink, SplitStream},
+    Sink, SinkExt, Stream, StreamExt, TryStreamExt,
+};
+use serde::Deserialize;
 use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::{mpsc, oneshot},
+    io::{AsyncRead, AsyncWrite},
+    sync::oneshot,
 };
+use tokio_rustls::TlsAcceptor;
 use tokio_tungstenite::WebSocketStream;
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig};
+
+use crate::{
+    auth,
+    control::{Control, DeliveryStatus},
+    heartbeat::LastSeen,
+    is_unix_address, is_valid_instance,
+    message::{self, Header, Message},
+    transport_for, ApiSpecifier, Codec, Handshake, ObserverIdentification, Registration,
+    ServerIdentification, Transport, PROTOCOL_VERSION,
+};
+
+use super::{
+    history::History,
+    limits::{LimitStats, Limiter},
+    metrics::{self, Dropped, HandshakeFailure, Metrics},
+    queue::{self, QueueStats, Rx, Tx},
+    routing::{
+        next_connection_id, ConnectionId, Observer, Observers, Peer, Requests, RoutingTable,
+    },
+    store::Store,
+    tls, AclEntry, LimitPolicy, ServerConfig, Shutdown,
+};
 
-use crate::{message::Message, ApiSpecifier, Handshake};
+#[cfg(unix)]
+use crate::Unix;
 
-type Tx = mpsc::UnboundedSender<tungstenite::Message>;
+type Connections = Arc<Mutex<Vec<Tx>>>;
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How far past Limits::max_message_size a message that's going to be dropped is still read.
+const OVERSIZED_MESSAGE_MARGIN: usize = 64 * 1024;
 
 pub struct Runner {
-    on_cancel: oneshot::Receiver<()>,
-    tx_sender: mpsc::UnboundedSender<Tx>,
+    config: ServerConfig,
+    on_cancel: oneshot::Receiver<Shutdown>,
+    connections: Connections,
+    metrics: Arc<Metrics>,
+    transport: Option<Arc<dyn Transport>>,
 }
 
 impl Runner {
-    pub fn new(on_cancel: oneshot::Receiver<()>, tx_sender: mpsc::UnboundedSender<Tx>) -> Runner {
+    pub(crate) fn new(config: ServerConfig, on_cancel: oneshot::Receiver<Shutdown>) -> Runner {
         Runner {
+            config,
             on_cancel,
-            tx_sender,
+            connections: Connections::default(),
+            metrics: Arc::default(),
+            transport: None,
         }
     }
 
+    pub fn with_transport<T>(mut self, transport: T) -> Runner
+    where
+        T: Transport + 'static,
+    {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    pub fn limit_stats(&self) -> Arc<LimitStats> {
+        self.metrics.limit_stats.clone()
+    }
+
+    pub fn queue_stats(&self) -> Arc<QueueStats> {
+        self.metrics.queue_stats.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    // Listens on each of a comma-separated list of addresses, e.g.
+    // "0.0.0.0:8080,unix:///run/bjorn/ws.sock".
     pub async fn run(self, addr: String) {
-        tokio::select! {
-            _ = tokio::spawn(listen(addr, self.tx_sender)) => {}
-            _ = self.on_cancel => {}
+        let shutdown_timeout = self
+            .config
+            .shutdown_timeout
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+
+        let mut listener = tokio::spawn(listen(
+            addr,
+            self.transport,
+            self.config,
+            self.connections.clone(),
+            self.metrics,
+        ));
+
+        let shutdown = tokio::select! {
+            _ = &mut listener => return,
+            shutdown = self.on_cancel => shutdown,
         };
+
+        listener.abort();
+
+        if let Ok(shutdown) = shutdown {
+            let timeout = match shutdown.drain {
+                true => shutdown_timeout,
+                false => Duration::ZERO,
+            };
+
+            close_connections(&self.connections, shutdown, timeout).await;
+        }
+    }
+}
+
+// Close frames queue up behind whatever is already waiting for each peer, so every
+// connection is flushed before it is closed.
+async fn close_connections(connections: &Connections, shutdown: Shutdown, timeout: Duration) {
+    let connections: Vec<Tx> = connections
+        .lock()
+        .unwrap()
+        .drain(..)
+        .filter(|tx| !tx.is_closed())
+        .collect();
+
+    for tx in &connections {
+        tx.send(tungstenite::Message::Close(Some(CloseFrame {
+            code: shutdown.code,
+            reason: shutdown.reason.into(),
+        })));
+    }
+
+    if timeout.is_zero() {
+        return;
     }
+
+    println!(
+        "Draining {} connection(s) before shutting down...",
+        connections.len()
+    );
+
+    let closed = future::join_all(connections.iter().map(|tx| tx.closed()));
+    if tokio::time::timeout(timeout, closed).await.is_err() {
+        let pending = connections.iter().filter(|tx| !tx.is_closed()).count();
+        println!("Shutdown timeout passed with {pending} connection(s) still draining");
+    }
+}
+
+async fn listen(
+    addrs: String,
+    transport: Option<Arc<dyn Transport>>,
+    config: ServerConfig,
+    connections: Connections,
+    metrics: Arc<Metrics>,
+) {
+    let config = Arc::new(config);
+
+    let routes = Routes {
+        emitters: Arc::default(),
+        handlers: Arc::default(),
+        observers: Arc::default(),
+        requests: Arc::default(),
+        store: config.store.clone().map(|store_config| {
+            Arc::new(
+                Store::open(store_config)
+                    .unwrap_or_else(|e| panic!("Error opening message store: {e}")),
+            )
+        }),
+        history: (!config.history.is_empty())
+            .then(|| Arc::new(History::new(config.history.clone()))),
+        metrics,
+    };
+
+    let acceptor = config.tls.as_ref().map(|tls_config| {
+        tls::acceptor(tls_config).unwrap_or_else(|e| panic!("Error loading TLS config: {e}"))
+    });
+
+    let listeners = addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| {
+            let transport = transport
+                .clone()
+                .unwrap_or_else(|| default_transport(addr, &config));
+
+            // Unix sockets are guarded by filesystem permissions and never leave the host.
+            let acceptor = match is_unix_address(addr) {
+                true => None,
+                false => acceptor.clone(),
+            };
+
+            accept(
+                addr.to_string(),
+                transport,
+                acceptor,
+                config.clone(),
+                routes.clone(),
+                connections.clone(),
+            )
+        });
+
+    let metrics_endpoint = async {
+        if let Some(addr) = &config.metrics_address {
+            metrics::serve(addr.clone(), routes.metrics.clone()).await;
+        }
+    };
+
+    future::join(future::join_all(listeners), metrics_endpoint).await;
 }
 
-async fn listen(addr: String, tx_sender: mpsc::UnboundedSender<Tx>) {
-    let emitters = Clients::new(Mutex::new(HashMap::new()));
-    let handlers = Clients::new(Mutex::new(HashMap::new()));
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn default_transport(addr: &str, config: &ServerConfig) -> Arc<dyn Transport> {
+    #[cfg(unix)]
+    if is_unix_address(addr) {
+        return Arc::new(Unix {
+            mode: config.unix_socket_mode,
+        });
+    }
 
-    let listener = TcpListener::bind(&addr)
+    transport_for(addr)
+}
+
+async fn accept(
+    addr: String,
+    transport: Arc<dyn Transport>,
+    acceptor: Option<TlsAcceptor>,
+    config: Arc<ServerConfig>,
+    routes: Routes,
+    connections: Connections,
+) {
+    let mut listener = transport
+        .bind(&addr)
         .await
-        .expect("Error binding to specified address");
+        .unwrap_or_else(|e| panic!("Error binding to {addr}: {e}"));
     println!("Listening on: {addr}");
 
     while let Ok((stream, addr)) = listener.accept().await {
-        tokio::task::spawn(handle_connection(
-            emitters.clone(),
-            handlers.clone(),
-            stream,
-            addr,
-            tx_sender.clone(),
-        ));
+        let config = config.clone();
+        let routes = routes.clone();
+        let connections = connections.clone();
+
+        match &acceptor {
+            Some(acceptor) => {
+                let acceptor = acceptor.clone();
+                tokio::task::spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            handle_connection(config, routes, stream, addr, connections).await
+                        }
+                        Err(e) => {
+                            println!("TLS handshake with {addr} failed: {e}");
+                            routes
+                                .metrics
+                                .count_handshake_failure(HandshakeFailure::Tls);
+                        }
+                    }
+                });
+            }
+            None => {
+                tokio::task::spawn(handle_connection(config, routes, stream, addr, connections));
+            }
+        }
     }
 }
 
-type Clients = Arc<Mutex<HashMap<ApiSpecifier, Vec<Tx>>>>;
+#[derive(Clone)]
+struct Routes {
+    emitters: Arc<RoutingTable>,
+    handlers: Arc<RoutingTable>,
+    observers: Arc<Observers>,
+    requests: Arc<Requests>,
+    store: Option<Arc<Store>>,
+    history: Option<Arc<History>>,
+    metrics: Arc<Metrics>,
+}
 
-async fn handle_connection(
-    emitters: Clients,
-    handlers: Clients,
-    raw_stream: TcpStream,
-    addr: SocketAddr,
-    tx_sender: mpsc::UnboundedSender<Tx>,
-) {
-    println!("Incoming TCP connection from: {addr}");
+impl Routes {
+    fn sides(&self, api_specifier: &ApiSpecifier) -> (&RoutingTable, &RoutingTable) {
+        match api_specifier {
+            ApiSpecifier::Emits(_) => (&self.emitters, &self.handlers),
+            ApiSpecifier::Handles(_) => (&self.handlers, &self.emitters),
+        }
+    }
+}
 
-    let ws_stream = tokio_tungstenite::accept_async(raw_stream)
-        .await
-        .expect("Error during the websocket handshake occurred");
+enum Role {
+    Client {
+        apis: Vec<Registration>,
+        identity: Option<String>,
+        codecs: Vec<Codec>,
+    },
+    Observer {
+        subscriptions: Vec<String>,
+    },
+}
+
+type Incoming =
+    Pin<Box<dyn Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Send>>;
+
+struct Session<S> {
+    id: ConnectionId,
+    config: Arc<ServerConfig>,
+    outgoing: SplitSink<WebSocketStream<S>, tungstenite::Message>,
+    incoming: SplitStream<WebSocketStream<S>>,
+    addr: String,
+    connections: Connections,
+    metrics: Arc<Metrics>,
+}
+
+async fn handle_connection<S>(
+    config: Arc<ServerConfig>,
+    routes: Routes,
+    raw_stream: S,
+    addr: String,
+    connections: Connections,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    println!("Incoming connection from: {addr}");
+
+    // Dropping an oversized message means reading it to find out who to tell, but only up to
+    // a point: anything much bigger than the limit ends the connection either way.
+    let ws_config = config.limits.max_message_size.map(|max_message_size| {
+        let hard_limit = match config.limits.policy {
+            LimitPolicy::Disconnect => max_message_size,
+            LimitPolicy::Drop => max_message_size.saturating_add(OVERSIZED_MESSAGE_MARGIN),
+        };
+        WebSocketConfig {
+            max_message_size: Some(hard_limit),
+            max_frame_size: Some(hard_limit),
+            ..WebSocketConfig::default()
+        }
+    });
+
+    let metrics = routes.metrics.clone();
+    let ws_stream = match tokio_tungstenite::accept_async_with_config(raw_stream, ws_config).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            println!("WebSocket handshake with {addr} failed: {e}");
+            metrics.count_handshake_failure(HandshakeFailure::WebSocket);
+            return;
+        }
+    };
 
     let (mut outgoing, mut incoming) = ws_stream.split();
 
+    let challenge = auth::generate_challenge();
+
     println!("Attempting to send handshake token...");
-    if let Err(_) = outgoing.send(Handshake::ServerIdentification.into()).await {
+    if outgoing
+        .send(
+            Handshake::ServerIdentification(ServerIdentification {
+                challenge: challenge.clone(),
+                protocol_version: PROTOCOL_VERSION,
+            })
+            .into(),
+        )
+        .await
+        .is_err()
+    {
         println!("Couldn't send handshake token");
+        metrics.count_handshake_failure(HandshakeFailure::Io);
         return;
     }
 
@@ -83,201 +380,833 @@ async fn handle_connection(
         Some(Ok(msg)) => msg,
         val => {
             println!("Couldn't get handshake response: {val:?}");
+            metrics.count_handshake_failure(HandshakeFailure::Io);
             return;
         }
     };
 
-    let api_specifier = match Handshake::try_from(handshake_response) {
-        Ok(Handshake::ClientIdentification(api_specifier)) => api_specifier,
+    // Older clients identify themselves in shapes this version can't parse, so the version is
+    // read on its own first to tell them why they're turned away.
+    if let Some(protocol_version) = protocol_version(&handshake_response) {
+        if protocol_version != PROTOCOL_VERSION {
+            println!("Unsupported protocol version {protocol_version}: {addr}");
+            metrics.count_handshake_failure(HandshakeFailure::Version);
+            reject(
+                &mut outgoing,
+                &format!(
+                    "Unsupported protocol version {protocol_version} (server speaks {PROTOCOL_VERSION})"
+                ),
+            )
+            .await;
+            return;
+        }
+    }
+
+    let (role, proof) = match Handshake::try_from(handshake_response) {
+        Ok(Handshake::ClientIdentification {
+            apis,
+            proof,
+            identity,
+            codecs,
+            ..
+        }) => (
+            Role::Client {
+                apis,
+                identity,
+                codecs,
+            },
+            proof,
+        ),
+        Ok(Handshake::Web(ObserverIdentification {
+            subscriptions,
+            proof,
+            ..
+        })) => (Role::Observer { subscriptions }, proof),
         Ok(handshake_response) => {
             println!("Invalid handshake response: {handshake_response:?}");
+            metrics.count_handshake_failure(HandshakeFailure::Invalid);
+            reject(&mut outgoing, "Invalid handshake response").await;
             return;
         }
         Err(e) => {
             println!("Invalid client type: {e}");
+            metrics.count_handshake_failure(HandshakeFailure::Invalid);
+            reject(&mut outgoing, "Invalid client type").await;
             return;
         }
     };
 
-    println!("WebSocket connection established: {api_specifier:?} ({addr})");
-
-    let (tx, mut rx) = mpsc::unbounded_channel();
-    tx_sender.send(tx.clone()).unwrap();
-
-    let broadcast_incoming = async {
-        match api_specifier {
-            ApiSpecifier::Emits(_) => {
-                handle_emitter(
-                    emitters.clone(),
-                    handlers.clone(),
-                    api_specifier.clone(),
-                    tx.clone(),
-                    incoming,
-                )
-                .await
+    let acl_entry = match (&role, &config.acl) {
+        (Role::Client { identity, .. }, Some(acl)) => {
+            match identity.as_deref().and_then(|identity| acl.entry(identity)) {
+                Some(entry) => Some(entry.clone()),
+                None => {
+                    println!("ACL violation: unknown identity {identity:?} ({addr})");
+                    metrics.count_handshake_failure(HandshakeFailure::Unauthorized);
+                    reject(&mut outgoing, "Authentication failed").await;
+                    return;
+                }
             }
-            ApiSpecifier::Handles(_) => {
-                handle_handler(
-                    emitters.clone(),
-                    handlers.clone(),
-                    api_specifier.clone(),
-                    tx.clone(),
-                    incoming,
-                )
-                .await
+        }
+        _ => None,
+    };
+
+    let secret = match role {
+        // With an ACL, every identity proves its own secret rather than the shared one.
+        Role::Client { .. } => match &acl_entry {
+            Some(entry) => Some(&entry.secret),
+            None => config.secret.as_ref(),
+        },
+        Role::Observer { .. } => match (&config.observer_secret, config.anonymous_observers) {
+            (Some(secret), _) => Some(secret),
+            (None, true) => None,
+            // The clients' secrets can't be shared with a dashboard running in a browser, so
+            // a server that has them only lets observers in once they're configured.
+            (None, false) if config.secret.is_some() || config.acl.is_some() => {
+                println!(
+                    "Observer rejected, set BJORN_WS_OBSERVER_SECRET or \
+                     BJORN_WS_ANONYMOUS_OBSERVERS to allow it: {addr}"
+                );
+                metrics.count_handshake_failure(HandshakeFailure::Unauthorized);
+                reject(&mut outgoing, "Observers aren't allowed").await;
+                return;
             }
+            (None, false) => None,
+        },
+    };
+
+    if let Some(secret) = secret {
+        let authenticated = proof
+            .map(|proof| auth::verify(secret, &challenge, &proof))
+            .unwrap_or(false);
+
+        if !authenticated {
+            println!("Authentication failed: {addr}");
+            metrics.count_handshake_failure(HandshakeFailure::Unauthorized);
+            reject(&mut outgoing, "Authentication failed").await;
+            return;
         }
+    }
+
+    let session = Session {
+        id: next_connection_id(),
+        config,
+        outgoing,
+        incoming,
+        addr,
+        connections,
+        metrics,
     };
 
-    let receive_from_others = async move {
-        loop {
-            let message = match rx.recv().await {
-                Some(msg) => msg,
-                None => break,
-            };
+    match role {
+        Role::Client {
+            apis,
+            identity,
+            codecs,
+        } => serve_client(session, routes, apis, identity, codecs, acl_entry).await,
+        Role::Observer { subscriptions } => serve_observer(session, routes, subscriptions).await,
+    }
+}
+
+// Only the part of an identification every protocol version has had.
+#[derive(Deserialize)]
+enum VersionedIdentification {
+    ClientIdentification(Versioned),
+    Web(Versioned),
+}
+
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default)]
+    protocol_version: u32,
+}
+
+fn protocol_version(handshake_response: &tungstenite::Message) -> Option<u32> {
+    let json = handshake_response.to_text().ok()?;
+
+    match serde_json::from_str(json).ok()? {
+        VersionedIdentification::ClientIdentification(versioned)
+        | VersionedIdentification::Web(versioned) => Some(versioned.protocol_version),
+    }
+}
 
-            outgoing.send(message).await.unwrap_or_default();
+async fn serve_client<S>(
+    mut session: Session<S>,
+    routes: Routes,
+    apis: Vec<Registration>,
+    identity: Option<String>,
+    codecs: Vec<Codec>,
+    acl_entry: Option<AclEntry>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let addr = session.addr.clone();
+    let api_specifiers = apis
+        .iter()
+        .map(|api| &api.api_specifier)
+        .collect::<Vec<_>>();
+    let peer = match &identity {
+        Some(identity) => format!("{identity} {api_specifiers:?} ({addr})"),
+        None => format!("{api_specifiers:?} ({addr})"),
+    };
+
+    if let Some(entry) = &acl_entry {
+        if let Some(api) = apis.iter().find(|api| !entry.allows(&api.api_specifier)) {
+            println!(
+                "ACL violation: {peer} may not register {:?}",
+                api.api_specifier
+            );
+            routes
+                .metrics
+                .count_handshake_failure(HandshakeFailure::Registration);
+            reject(
+                &mut session.outgoing,
+                &format!("Not allowed to register {:?}", api.api_specifier),
+            )
+            .await;
+            return;
         }
+    }
+
+    if let Some(reason) = invalid_registrations(&routes, &apis) {
+        println!("{reason}: {peer}");
+        routes
+            .metrics
+            .count_handshake_failure(HandshakeFailure::Registration);
+        reject(&mut session.outgoing, &reason).await;
+        return;
+    }
+
+    // Every codec is supported, so the client's first preference wins. Clients that don't
+    // offer any predate negotiation and get a plain acceptance.
+    let (codec, acceptance) = match codecs.first() {
+        Some(&codec) => (codec, Handshake::Negotiated { codec }),
+        None => (Codec::Json, Handshake::Accepted),
     };
 
-    tokio::select! {
-        _ = broadcast_incoming => {}
-        _ = tokio::spawn(receive_from_others) => {}
+    if session.outgoing.send(acceptance.into()).await.is_err() {
+        println!("Couldn't send handshake acceptance");
+        routes.metrics.count_handshake_failure(HandshakeFailure::Io);
+        return;
     }
 
-    match api_specifier {
-        ApiSpecifier::Emits(_) => {
-            if let Some(emitters) = emitters.lock().unwrap().get_mut(&api_specifier) {
-                if let Some((index, _)) = emitters
-                    .iter()
-                    .enumerate()
-                    .find(|(_, e)| e.same_channel(&tx))
-                {
-                    emitters.remove(index);
-                }
+    println!("WebSocket connection established: {peer}");
+
+    let id = session.id;
+    let (tx, rx) = session.queue(&peer);
+
+    for Registration {
+        api_specifier,
+        schema,
+        replay,
+    } in &apis
+    {
+        let (own_clients, peer_clients) = routes.sides(api_specifier);
+
+        tx.send(
+            Control::PeerSchemas {
+                target: api_specifier.target().into(),
+                schemas: peer_clients.schemas(api_specifier.target()),
             }
-        }
-        ApiSpecifier::Handles(_) => {
-            if let Some(handlers) = handlers.lock().unwrap().get_mut(&api_specifier) {
-                if let Some((index, _)) = handlers
-                    .iter()
-                    .enumerate()
-                    .find(|(_, h)| h.same_channel(&tx))
-                {
-                    handlers.remove(index);
+            .into(),
+        );
+
+        {
+            let own_clients_lock = own_clients.lock();
+
+            if let ApiSpecifier::Handles(target) = api_specifier {
+                let stored = match &routes.store {
+                    Some(store) => store.take(target),
+                    None => vec![],
+                };
+                if !stored.is_empty() {
+                    println!(
+                        "Delivering {} stored {target} message(s) to {addr}",
+                        stored.len()
+                    );
+                }
+
+                // Stored messages were never delivered, so they're sent as live messages
+                // after the history, which includes them too.
+                if let (true, Some(history)) = (*replay, &routes.history) {
+                    let replayed = history
+                        .replay(target)
+                        .into_iter()
+                        .filter(|message| !stored.iter().any(|stored| stored.id == message.id))
+                        .collect::<Vec<_>>();
+                    if !replayed.is_empty() {
+                        println!("Replaying {} {target} message(s) to {addr}", replayed.len());
+                    }
+
+                    for message in replayed {
+                        tx.send(codec.encode(message));
+                    }
+                }
+
+                for message in stored {
+                    tx.send(codec.encode(message));
                 }
             }
+
+            own_clients_lock.insert(
+                api_specifier.target(),
+                Peer {
+                    id,
+                    tx: tx.clone(),
+                    schema: schema.clone(),
+                    codec,
+                },
+            );
         }
+        routes.metrics.count_registered(api_specifier);
+        announce_schemas(own_clients, api_specifier, peer_clients);
     }
 
-    println!("{api_specifier:?} ({addr}) disconnected");
+    let registered = apis
+        .iter()
+        .map(|api| {
+            (
+                api.api_specifier.target().to_string(),
+                api.api_specifier.clone(),
+            )
+        })
+        .collect();
+
+    let client = Client {
+        id,
+        tx: tx.clone(),
+        sender: identity.unwrap_or(addr),
+        registered,
+        acl_entry,
+    };
+
+    session
+        .run(peer.clone(), rx, |incoming, limiter| {
+            handle_client(routes.clone(), client, incoming, limiter)
+        })
+        .await;
+
+    routes.requests.forget(id);
+    for Registration { api_specifier, .. } in &apis {
+        let (own_clients, peer_clients) = routes.sides(api_specifier);
+
+        own_clients.lock().remove(api_specifier.target(), id);
+        routes.metrics.count_unregistered(api_specifier);
+        announce_schemas(own_clients, api_specifier, peer_clients);
+    }
+
+    println!("{peer} disconnected");
 }
 
-async fn handle_emitter(
-    emitters: Clients,
-    handlers: Clients,
-    api_specifier: ApiSpecifier,
-    tx: Tx,
-    incoming: SplitStream<WebSocketStream<TcpStream>>,
-) {
+fn invalid_registrations(routes: &Routes, apis: &[Registration]) -> Option<String> {
+    if apis.is_empty() {
+        return Some("No APIs registered".into());
+    }
+
+    for (
+        i,
+        Registration {
+            api_specifier,
+            schema,
+            ..
+        },
+    ) in apis.iter().enumerate()
     {
-        let mut emitters_lock = emitters.lock().unwrap();
+        let target = api_specifier.target();
 
-        match emitters_lock.get_mut(&api_specifier) {
-            Some(emitters) => {
-                emitters.push(tx);
-            }
-            None => {
-                emitters_lock.insert(api_specifier, vec![tx]);
+        if api_specifier.api_id().is_empty() {
+            return Some(format!("\"{target}\" doesn't name an API"));
+        }
+        if let Some(instance) = api_specifier.instance() {
+            if !is_valid_instance(instance) {
+                return Some(format!(
+                    "Invalid instance name \"{instance}\" for {target}: use letters, digits, '-' or '_'"
+                ));
             }
         }
+
+        if apis[..i]
+            .iter()
+            .any(|api| api.api_specifier.target() == target)
+        {
+            return Some(format!("{target} is registered more than once"));
+        }
+
+        let (_, peer_clients) = routes.sides(api_specifier);
+        let peer_schemas = peer_clients.schemas(target);
+        if let Some(peer_schema) = peer_schemas.iter().find(|s| s.version != schema.version) {
+            return Some(format!(
+                "{target} schema v{} is incompatible with connected peers (v{})",
+                schema.version, peer_schema.version
+            ));
+        }
     }
 
-    incoming
-        .try_for_each(move |msg| {
-            let handlers = handlers.clone();
-            async move {
-                let ws_message = match Message::try_from(msg.clone()) {
-                    Ok(msg) => msg,
-                    Err(_) => {
-                        eprintln!("Unknown message, ignoring: {msg:?}");
-                        return Ok(());
-                    }
-                };
+    None
+}
+
+async fn serve_observer<S>(mut session: Session<S>, routes: Routes, subscriptions: Vec<String>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let addr = session.addr.clone();
 
-                let handlers = handlers.lock().unwrap();
-                let handlers = match handlers.get(&ws_message.target_api_specifier()) {
-                    Some(handler) => handler,
-                    None => {
-                        println!(
-                            "No {:?} client connected.",
-                            ws_message.target_api_specifier()
-                        );
-                        return Ok(());
+    if session
+        .outgoing
+        .send(Handshake::Accepted.into())
+        .await
+        .is_err()
+    {
+        println!("Couldn't send handshake acceptance");
+        routes.metrics.count_handshake_failure(HandshakeFailure::Io);
+        return;
+    }
+
+    println!("Observer connected: {subscriptions:?} ({addr})");
+
+    let peer = format!("Observer ({addr})");
+    let id = session.id;
+    let (tx, rx) = session.queue(&peer);
+
+    routes.observers.insert(Observer {
+        id,
+        tx,
+        subscriptions,
+    });
+    routes.metrics.count_observer(true);
+
+    session
+        .run(peer, rx, |incoming, mut limiter| {
+            incoming.try_for_each(move |msg| {
+                future::ready(match limiter.check_connection().admits() {
+                    Some(true) => {
+                        println!("Observers can't send messages, ignoring: {msg:?}");
+                        Ok(())
                     }
-                };
+                    Some(false) => Ok(()),
+                    None => Err(tungstenite::Error::ConnectionClosed),
+                })
+            })
+        })
+        .await;
 
-                for handler in handlers {
-                    handler.send(ws_message.clone().into()).unwrap();
-                }
+    routes.observers.remove(id);
+    routes.metrics.count_observer(false);
+
+    println!("Observer ({addr}) disconnected");
+}
+
+impl<S> Session<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn queue(&self, peer: &str) -> (Tx, Rx) {
+        let (tx, rx) = queue::channel(self.config.clone(), self.metrics.clone(), peer.into());
+
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|tx| !tx.is_closed());
+        connections.push(tx.clone());
+
+        (tx, rx)
+    }
+
+    async fn run<F, Fut>(self, peer: String, mut rx: Rx, handle_incoming: F)
+    where
+        F: FnOnce(Incoming, Limiter) -> Fut,
+        Fut: Future,
+    {
+        let Session {
+            config,
+            mut outgoing,
+            incoming,
+            metrics,
+            ..
+        } = self;
+
+        let limit_stats = metrics.limit_stats.clone();
+
+        let limiter = Limiter::new(config.limits.clone(), limit_stats.clone(), peer.clone());
+
+        let last_seen = LastSeen::new();
+        let incoming: Incoming = {
+            let last_seen = last_seen.clone();
+            let peer = peer.clone();
+            Box::pin(
+                incoming
+                    .inspect(move |msg| {
+                        last_seen.touch();
+
+                        if let Err(tungstenite::Error::Capacity(e)) = msg {
+                            println!("{peer} sent an oversized message ({e}), disconnecting");
+                            limit_stats.count_oversized();
+                        }
+                    })
+                    .try_filter(|msg| {
+                        future::ready(!msg.is_ping() && !msg.is_pong() && !msg.is_close())
+                    }),
+            )
+        };
+
+        let heartbeat = config.heartbeat;
+        let receive_from_others = async move {
+            let mut ticker = heartbeat.ticker();
+            loop {
+                tokio::select! {
+                    message = rx.recv() => match message {
+                        Some(message) => {
+                            let closing = message.is_close();
+                            tokio::select! {
+                                sent = outgoing.send(message) => sent.unwrap_or_default(),
+                                _ = rx.overflowed() => break,
+                            }
+                            if closing {
+                                break;
+                            }
+                        }
+                        None => break,
+                    },
+                    _ = ticker.tick() => {
+                        if heartbeat.is_overdue(&last_seen) {
+                            println!("{peer} missed its heartbeat, dropping it");
+                            break;
+                        }
 
-                Ok(())
+                        outgoing
+                            .send(tungstenite::Message::Ping(vec![]))
+                            .await
+                            .unwrap_or_default();
+                    }
+                }
             }
-        })
+        };
+
+        let mut receive_from_others = tokio::spawn(receive_from_others);
+        tokio::select! {
+            _ = handle_incoming(incoming, limiter) => receive_from_others.abort(),
+            _ = &mut receive_from_others => {}
+        }
+    }
+}
+
+fn announce_schemas(
+    clients: &RoutingTable,
+    api_specifier: &ApiSpecifier,
+    peer_clients: &RoutingTable,
+) {
+    let target = api_specifier.target();
+    let control: tungstenite::Message = Control::PeerSchemas {
+        target: target.into(),
+        schemas: clients.schemas(target),
+    }
+    .into();
+
+    if let Some(peers) = peer_clients.peers(target) {
+        for peer in peers.iter() {
+            peer.tx.send(control.clone());
+        }
+    }
+}
+
+fn notify_observers(observers: &Observers, from: &ApiSpecifier, message: &Message) {
+    let observers = observers.snapshot();
+    let mut observers = observers
+        .iter()
+        .filter(|observer| observer.observes(&message.target))
+        .peekable();
+
+    if observers.peek().is_none() {
+        return;
+    }
+
+    let control: tungstenite::Message = Control::Observed {
+        from: from.clone(),
+        message: message.clone(),
+    }
+    .into();
+
+    for observer in observers {
+        observer.tx.send_message(&message.target, control.clone());
+    }
+}
+
+async fn reject<S>(outgoing: &mut S, reason: &str)
+where
+    S: Sink<tungstenite::Message> + Unpin,
+{
+    outgoing
+        .send(Handshake::Rejected(reason.into()).into())
+        .await
+        .unwrap_or_default();
+
+    outgoing
+        .send(tungstenite::Message::Close(Some(CloseFrame {
+            code: CloseCode::Policy,
+            reason: reason.to_string().into(),
+        })))
         .await
         .unwrap_or_default();
 }
 
-async fn handle_handler(
-    emitters: Clients,
-    handlers: Clients,
-    api_specifier: ApiSpecifier,
+// The connection a client's frames come in on.
+struct Client {
+    id: ConnectionId,
     tx: Tx,
-    incoming: SplitStream<WebSocketStream<TcpStream>>,
+    sender: String,
+    registered: HashMap<String, ApiSpecifier>,
+    acl_entry: Option<AclEntry>,
+}
+
+async fn handle_client(routes: Routes, client: Client, incoming: Incoming, mut limiter: Limiter) {
+    incoming
+        .try_for_each(move |msg| {
+            future::ready(
+                handle_frame(&routes, &client, &mut limiter, msg)
+                    .ok_or(tungstenite::Error::ConnectionClosed),
+            )
+        })
+        .await
+        .unwrap_or_default();
+}
+
+fn handle_frame(
+    routes: &Routes,
+    client: &Client,
+    limiter: &mut Limiter,
+    msg: tungstenite::Message,
+) -> Option<()> {
+    // An oversized message doesn't count against the rate as well.
+    let refused = if !limiter.check_size(msg.len()).admits()? {
+        Some(DeliveryStatus::TooLarge)
+    } else if !limiter.check_connection().admits()? {
+        Some(DeliveryStatus::RateLimited)
+    } else {
+        None
+    };
+
+    // A refused message only needs enough of it read to say so, which is done without
+    // copying it or keeping its content.
+    if let Some(status) = refused {
+        match Header::try_from(&msg) {
+            Ok(header) => acknowledge(&client.tx, &header.target, header.delivery_id, status),
+            Err(e) => println!("Unknown message, ignoring: {e}"),
+        }
+        return Some(());
+    }
+
+    let ws_message = match Message::try_from(msg.clone()) {
+        Ok(msg) => msg,
+        Err(_) => {
+            println!("Unknown message, ignoring: {msg:?}");
+            return Some(());
+        }
+    };
+
+    let api_specifier = match client.registered.get(&ws_message.target) {
+        Some(api_specifier) => api_specifier,
+        None => {
+            println!(
+                "{} isn't registered on this connection, ignoring message",
+                ws_message.target
+            );
+            return Some(());
+        }
+    };
+
+    // Registration already checked the ACL, so this only catches a frame that slipped past it.
+    if let Some(entry) = &client.acl_entry {
+        if !entry.allows(api_specifier) {
+            println!(
+                "ACL violation: {} may not send {api_specifier:?}, dropping message",
+                client.sender
+            );
+            routes
+                .metrics
+                .count_dropped(&ws_message.target, Dropped::Forbidden);
+            acknowledge(
+                &client.tx,
+                &ws_message.target,
+                ws_message.delivery_id,
+                DeliveryStatus::Forbidden,
+            );
+            return Some(());
+        }
+    }
+
+    if !limiter.check_target(&ws_message.target).admits()? {
+        routes
+            .metrics
+            .count_dropped(&ws_message.target, Dropped::RateLimited);
+        acknowledge(
+            &client.tx,
+            &ws_message.target,
+            ws_message.delivery_id,
+            DeliveryStatus::RateLimited,
+        );
+        return Some(());
+    }
+
+    routes.metrics.count_routed(&ws_message.target);
+
+    // The delivery id is between the sender and the server, so it's kept back from the
+    // recipients along with anything else only the server may set.
+    let delivery_id = ws_message.delivery_id;
+    let ws_message = Message {
+        id: ws_message.id.or_else(|| Some(rand::random())),
+        timestamp: ws_message.timestamp.or_else(|| Some(message::now())),
+        sender: Some(client.sender.clone()),
+        delivery_id: None,
+        replayed: false,
+        ..ws_message
+    };
+
+    match api_specifier {
+        ApiSpecifier::Emits(_) => {
+            route_emitted(routes, client.id, &client.tx, ws_message, delivery_id)
+        }
+        ApiSpecifier::Handles(_) => route_reply(routes, ws_message),
+    }
+
+    Some(())
+}
+
+fn route_emitted(
+    routes: &Routes,
+    from: ConnectionId,
+    tx: &Tx,
+    ws_message: Message,
+    delivery_id: Option<u64>,
 ) {
-    {
-        let mut handlers_lock = handlers.lock().unwrap();
-        match handlers_lock.get_mut(&api_specifier) {
-            Some(handlers) => {
-                handlers.push(tx);
-            }
-            None => {
-                handlers_lock.insert(api_specifier, vec![tx]);
+    let target = ws_message.target.clone();
+    let correlation_id = ws_message.correlation_id;
+    if let Some(correlation_id) = correlation_id {
+        routes.requests.insert(correlation_id, from);
+    }
+    notify_observers(
+        &routes.observers,
+        &ws_message.source_api_specifier(),
+        &ws_message,
+    );
+
+    if let Some(history) = &routes.history {
+        history.record(&ws_message);
+    }
+
+    let status = match routes.handlers.peers(&ws_message.target) {
+        Some(handlers) => DeliveryStatus::Delivered(send_to(&handlers, &ws_message)),
+        None => {
+            // Registering takes the lock too, so a handler can't connect between finding
+            // there's none and storing the message, and miss it until the next one connects.
+            let handlers = routes.handlers.lock();
+            match handlers.peers(&ws_message.target) {
+                Some(handlers) => DeliveryStatus::Delivered(send_to(&handlers, &ws_message)),
+                None => {
+                    let target_api_specifier = ws_message.target_api_specifier();
+                    let stored = routes.store.as_ref().map(|store| store.push(ws_message));
+
+                    match stored {
+                        Some(true) => {
+                            println!("No {target_api_specifier:?} client connected, stored message for later delivery.");
+                            DeliveryStatus::Stored
+                        }
+                        _ => {
+                            println!("No {target_api_specifier:?} client connected.");
+                            if let Some(correlation_id) = correlation_id {
+                                routes.requests.take(correlation_id);
+                            }
+                            routes
+                                .metrics
+                                .count_dropped(target_api_specifier.target(), Dropped::NoRoute);
+                            DeliveryStatus::NoRoute
+                        }
+                    }
+                }
             }
         }
+    };
+
+    acknowledge(tx, &target, delivery_id, status);
+}
+
+fn acknowledge(tx: &Tx, target: &str, delivery_id: Option<u64>, status: DeliveryStatus) {
+    // There's one of these for every message, so they're held to the same limits.
+    if let Some(delivery_id) = delivery_id {
+        tx.send_message(
+            target,
+            Control::Delivery {
+                delivery_id,
+                status,
+            }
+            .into(),
+        );
     }
+}
 
-    incoming
-        .try_for_each(move |msg| {
-            let emitters = emitters.clone();
-            async move {
-                let ws_message = match Message::try_from(msg.clone()) {
-                    Ok(msg) => msg,
-                    Err(_) => {
-                        println!("Unknown message, ignoring: {msg:?}");
-                        return Ok(());
-                    }
-                };
+fn route_reply(routes: &Routes, ws_message: Message) {
+    notify_observers(
+        &routes.observers,
+        &ws_message.target_api_specifier(),
+        &ws_message,
+    );
 
-                let emitters = emitters.lock().unwrap();
-                let emitters = match emitters.get(&ws_message.target_api_specifier()) {
-                    Some(emitter) => emitter,
-                    None => {
-                        println!(
-                            "No {:?} client connected.",
-                            ws_message.target_api_specifier()
-                        );
-                        return Ok(());
-                    }
-                };
+    let emitters = match routes.emitters.peers(&ws_message.target) {
+        Some(emitters) => emitters,
+        None => {
+            if let Some(correlation_id) = ws_message.correlation_id {
+                routes.requests.take(correlation_id);
+            }
+            println!(
+                "No {:?} client connected.",
+                ws_message.source_api_specifier()
+            );
+            routes
+                .metrics
+                .count_dropped(&ws_message.target, Dropped::NoRoute);
+            return;
+        }
+    };
+
+    let correlation_id = match ws_message.correlation_id {
+        Some(correlation_id) => correlation_id,
+        None => {
+            send_to(&emitters, &ws_message);
+            return;
+        }
+    };
 
-                for emitter in emitters {
-                    emitter.send(ws_message.clone().into()).unwrap();
+    // Only the first reply to a request is passed on, to the connection that made it.
+    let requester = routes
+        .requests
+        .take(correlation_id)
+        .and_then(|from| emitters.iter().find(|emitter| emitter.id == from));
+    match requester {
+        Some(requester) => {
+            send_to(std::slice::from_ref(requester), &ws_message);
+        }
+        None => println!(
+            "Nobody is waiting for this {} reply, dropping it.",
+            ws_message.target
+        ),
+    }
+}
+
+// Returns how many peers the message was queued for: those that are too far behind miss
+// out. It's encoded once for each codec in use, however many peers there are.
+fn send_to(peers: &[Peer], message: &Message) -> usize {
+    let mut encoded: Vec<(Codec, tungstenite::Message)> = vec![];
+
+    peers
+        .iter()
+        .filter(|peer| {
+            let frame = match encoded.iter().find(|(codec, _)| *codec == peer.codec) {
+                Some((_, frame)) => frame.clone(),
+                None => {
+                    let frame = peer.codec.encode(message.clone());
+                    encoded.push((peer.codec, frame.clone()));
+                    frame
                 }
+            };
 
-                Ok(())
-            }
+            peer.tx.send_message(&message.target, frame)
         })
-        .await
-        .unwrap_or_default();
+        .count()
 }
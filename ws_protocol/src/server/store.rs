@@ -0,0 +1,343 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::{self, BufRead, BufReader, Write as _},
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::message::Message;
+
+use super::{configured_for, Retention, StoreConfig};
+
+// Stored messages are kept in memory as well as on disk, so routing never waits on the
+// disk: a writer thread catches the files up in the background.
+pub struct Store {
+    config: StoreConfig,
+    messages: Mutex<HashMap<String, VecDeque<Stored>>>,
+    writes: mpsc::Sender<Write>,
+    writer: Option<JoinHandle<()>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    stored_at: u64,
+    message: Message,
+}
+
+enum Write {
+    Append { target: String, line: String },
+    // Only needed once messages have been trimmed, to compact the file.
+    Rewrite { target: String, lines: Vec<String> },
+    Remove { target: String },
+}
+
+impl Store {
+    pub fn open(config: StoreConfig) -> io::Result<Store> {
+        fs::create_dir_all(&config.dir)?;
+
+        let mut messages = HashMap::new();
+        for entry in fs::read_dir(&config.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("jsonl") {
+                continue;
+            }
+
+            let target = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(target) => target.to_string(),
+                None => continue,
+            };
+            if let Some(retention) = configured_for(&config.retention, &target) {
+                let mut stored = load(&path, &target);
+                trim(&mut stored, retention, &target);
+                messages.insert(target, stored);
+            }
+        }
+
+        let (writes, pending) = mpsc::channel();
+        let dir = config.dir.clone();
+        let writer = thread::Builder::new()
+            .name("message-store".into())
+            .spawn(move || write_all(dir, pending))?;
+
+        Ok(Store {
+            config,
+            messages: Mutex::new(messages),
+            writes,
+            writer: Some(writer),
+        })
+    }
+
+    pub fn push(&self, message: Message) -> bool {
+        let retention = match self.retention(&message.target) {
+            Some(retention) if message.correlation_id.is_none() => retention,
+            _ => return false,
+        };
+
+        let target = message.target.clone();
+        let stored = Stored {
+            stored_at: now(),
+            message,
+        };
+        let line = serde_json::to_string(&stored).unwrap();
+
+        let mut messages = self.messages.lock().unwrap();
+        let messages = messages.entry(target.clone()).or_default();
+        messages.push_back(stored);
+
+        let write = match trim(messages, &retention, &target) {
+            true => Write::Rewrite {
+                target,
+                lines: messages
+                    .iter()
+                    .map(|stored| serde_json::to_string(stored).unwrap())
+                    .collect(),
+            },
+            false => Write::Append { target, line },
+        };
+
+        self.writes.send(write).is_ok()
+    }
+
+    pub fn take(&self, target: &str) -> Vec<Message> {
+        let retention = match self.retention(target) {
+            Some(retention) => retention,
+            None => return vec![],
+        };
+
+        // Held until the removal is queued, so it can't overtake a message stored after it.
+        let mut messages = self.messages.lock().unwrap();
+        let mut stored = match messages.remove(target) {
+            Some(stored) => stored,
+            None => return vec![],
+        };
+        trim(&mut stored, &retention, target);
+
+        let before = stored.len();
+        stored.retain(|stored| !stored.message.is_expired());
+        let expired = before - stored.len();
+        if expired > 0 {
+            println!("Discarding {expired} stored {target} message(s) past their sender's TTL");
+        }
+
+        self.writes
+            .send(Write::Remove {
+                target: target.into(),
+            })
+            .unwrap_or_default();
+
+        stored.into_iter().map(|stored| stored.message).collect()
+    }
+
+    fn retention(&self, target: &str) -> Option<Retention> {
+        configured_for(&self.config.retention, target).copied()
+    }
+}
+
+// Whatever is still queued for the writer gets to disk before the store goes away, so a
+// store opened on the same directory next sees everything this one held.
+impl Drop for Store {
+    fn drop(&mut self) {
+        let (closed, _) = mpsc::channel();
+        drop(std::mem::replace(&mut self.writes, closed));
+
+        if let Some(writer) = self.writer.take() {
+            writer.join().unwrap_or_default();
+        }
+    }
+}
+
+// Drops messages that are too old or too many, returning whether there were any.
+fn trim(stored: &mut VecDeque<Stored>, retention: &Retention, target: &str) -> bool {
+    let oldest = now().saturating_sub(retention.max_age.as_secs());
+    let expired = stored
+        .iter()
+        .take_while(|stored| stored.stored_at < oldest)
+        .count();
+    stored.drain(..expired);
+
+    let overflow = stored.len().saturating_sub(retention.max_messages);
+    if overflow > 0 {
+        println!("Store for {target} is full, discarding {overflow} oldest message(s)");
+        stored.drain(..overflow);
+    }
+
+    expired + overflow > 0
+}
+
+fn load(path: &Path, target: &str) -> VecDeque<Stored> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return VecDeque::new(),
+    };
+
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| match serde_json::from_str::<Stored>(&line) {
+            Ok(stored) => Some(stored),
+            Err(e) => {
+                println!("Skipping unreadable stored {target} message: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+fn write_all(dir: PathBuf, pending: mpsc::Receiver<Write>) {
+    let path = |target: &str| dir.join(format!("{target}.jsonl"));
+
+    for write in pending {
+        let (target, written) = match write {
+            Write::Append { target, line } => {
+                let written = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path(&target))
+                    .and_then(|mut file| writeln!(file, "{line}"));
+                (target, written)
+            }
+            Write::Rewrite { target, lines } => {
+                let written = rewrite(&path(&target), &lines);
+                (target, written)
+            }
+            Write::Remove { target } => {
+                fs::remove_file(path(&target)).unwrap_or_default();
+                continue;
+            }
+        };
+
+        if let Err(e) = written {
+            println!("Couldn't store {target} message: {e}");
+        }
+    }
+}
+
+fn rewrite(path: &Path, lines: &[String]) -> io::Result<()> {
+    let tmp_path = path.with_extension("jsonl.tmp");
+
+    let mut contents = String::new();
+    for line in lines {
+        contents.push_str(line);
+        contents.push('\n');
+    }
+
+    fs::write(&tmp_path, contents)?;
+    fs::rename(tmp_path, path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+    use crate::message::Message;
+
+    use super::{Retention, Store, StoreConfig};
+
+    struct Dir(PathBuf);
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).unwrap_or_default();
+        }
+    }
+
+    fn dir(name: &str) -> Dir {
+        let dir = std::env::temp_dir().join(format!("bjorn-store-{name}-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+        Dir(dir)
+    }
+
+    fn open(dir: &Dir) -> Store {
+        Store::open(StoreConfig {
+            dir: dir.0.clone(),
+            retention: HashMap::from([(
+                "echo".to_string(),
+                Retention {
+                    max_messages: 2,
+                    max_age: Duration::from_secs(60),
+                },
+            )]),
+        })
+        .unwrap()
+    }
+
+    fn push(store: &Store, content: &str) {
+        assert!(store.push(Message::new("echo".into(), content.into())));
+    }
+
+    fn take(store: &Store) -> Vec<String> {
+        store
+            .take("echo")
+            .into_iter()
+            .map(|message| message.content)
+            .collect()
+    }
+
+    #[test]
+    fn keeps_stored_messages_across_a_restart() {
+        let dir = dir("restart");
+
+        let store = open(&dir);
+        push(&store, "first");
+        push(&store, "second");
+        drop(store);
+
+        assert_eq!(take(&open(&dir)), ["first", "second"]);
+    }
+
+    #[test]
+    fn forgets_taken_messages_across_a_restart() {
+        let dir = dir("taken");
+
+        let store = open(&dir);
+        push(&store, "delivered");
+        assert_eq!(take(&store), ["delivered"]);
+        push(&store, "pending");
+        drop(store);
+
+        let store = open(&dir);
+        assert_eq!(take(&store), ["pending"]);
+        drop(store);
+
+        assert!(take(&open(&dir)).is_empty());
+    }
+
+    #[test]
+    fn keeps_trimmed_messages_trimmed_across_a_restart() {
+        let dir = dir("trimmed");
+
+        let store = open(&dir);
+        push(&store, "first");
+        push(&store, "second");
+        push(&store, "third");
+        drop(store);
+
+        assert_eq!(take(&open(&dir)), ["second", "third"]);
+    }
+
+    #[test]
+    fn drops_messages_past_their_ttl_when_taken() {
+        let dir = dir("expired");
+
+        let store = open(&dir);
+        assert!(store.push(Message {
+            expires_at: Some(crate::message::now() - 1),
+            ..Message::new("echo".into(), "stale".into())
+        }));
+        push(&store, "fresh");
+
+        assert_eq!(take(&store), ["fresh"]);
+    }
+}
@@ -0,0 +1,47 @@
+use std::{fs::File, io::BufReader, sync::Arc};
+
+use rustls::{Certificate, PrivateKey};
+use tokio_rustls::TlsAcceptor;
+
+use super::ServerTlsConfig;
+
+pub fn acceptor(config: &ServerTlsConfig) -> Result<TlsAcceptor, String> {
+    let certs = load_certs(&config.cert_path)?;
+    let key = load_key(&config.key_path)?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid TLS certificate or key: {e}"))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>, String> {
+    let file = File::open(path).map_err(|e| format!("Couldn't open {path}: {e}"))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| format!("Couldn't read certificates from {path}: {e}"))?;
+
+    match certs.len() {
+        0 => Err(format!("No certificates found in {path}")),
+        _ => Ok(certs.into_iter().map(Certificate).collect()),
+    }
+}
+
+fn load_key(path: &str) -> Result<PrivateKey, String> {
+    let file = File::open(path).map_err(|e| format!("Couldn't open {path}: {e}"))?;
+    let mut reader = BufReader::new(file);
+
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| format!("Couldn't read private key from {path}: {e}"))?
+        {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => break Err(format!("No private key found in {path}")),
+        }
+    }
+}
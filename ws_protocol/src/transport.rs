@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tungstenite::client::IntoClientRequest;
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+pub trait TransportStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> TransportStream for S {}
+
+#[async_trait]
+pub trait Transport: std::fmt::Debug + Send + Sync {
+    async fn connect(&self, url: &str) -> io::Result<Box<dyn TransportStream>>;
+
+    async fn bind(&self, addr: &str) -> io::Result<Box<dyn TransportListener>>;
+
+    // The URL sent in the WebSocket handshake, which has to use the ws or wss scheme.
+    fn request_url(&self, url: &str) -> String {
+        url.into()
+    }
+}
+
+#[async_trait]
+pub trait TransportListener: Send {
+    async fn accept(&mut self) -> io::Result<(Box<dyn TransportStream>, String)>;
+}
+
+pub const UNIX_SCHEME: &str = "unix://";
+
+pub fn is_unix_address(addr: &str) -> bool {
+    addr.starts_with(UNIX_SCHEME)
+}
+
+pub fn transport_for(addr: &str) -> Arc<dyn Transport> {
+    #[cfg(unix)]
+    if is_unix_address(addr) {
+        return Arc::new(Unix::default());
+    }
+
+    Arc::new(Tcp)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tcp;
+
+#[async_trait]
+impl Transport for Tcp {
+    async fn connect(&self, url: &str) -> io::Result<Box<dyn TransportStream>> {
+        Ok(Box::new(TcpStream::connect(authority(url)?).await?))
+    }
+
+    async fn bind(&self, addr: &str) -> io::Result<Box<dyn TransportListener>> {
+        Ok(Box::new(TcpListener::bind(addr).await?))
+    }
+}
+
+#[async_trait]
+impl TransportListener for TcpListener {
+    async fn accept(&mut self) -> io::Result<(Box<dyn TransportStream>, String)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((Box::new(stream), addr.to_string()))
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unix {
+    pub mode: Option<u32>,
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl Transport for Unix {
+    async fn connect(&self, url: &str) -> io::Result<Box<dyn TransportStream>> {
+        Ok(Box::new(UnixStream::connect(unix_path(url)).await?))
+    }
+
+    async fn bind(&self, addr: &str) -> io::Result<Box<dyn TransportListener>> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let path = PathBuf::from(unix_path(addr));
+
+        // Clear out a socket left behind by a server that didn't shut down cleanly, but
+        // never one that is still being served.
+        let is_socket = std::fs::symlink_metadata(&path)
+            .map(|metadata| metadata.file_type().is_socket())
+            .unwrap_or(false);
+        if is_socket {
+            if UnixStream::connect(&path).await.is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is already being served", path.display()),
+                ));
+            }
+            std::fs::remove_file(&path)?;
+        }
+
+        let listener = match self.mode {
+            Some(mode) => bind_with_mode(&path, mode)?,
+            None => UnixListener::bind(&path)?,
+        };
+
+        Ok(Box::new(UnixSocketListener {
+            listener,
+            path,
+            accepted: 0,
+        }))
+    }
+
+    fn request_url(&self, _url: &str) -> String {
+        "ws://localhost/".into()
+    }
+}
+
+// The socket is bound in a directory only we can reach and moved into place once it has its
+// mode, so nobody can connect while it still has the umask's permissions.
+#[cfg(unix)]
+fn bind_with_mode(path: &std::path::Path, mode: u32) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let dir = path.with_file_name(format!(".{file_name}.{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let bound = dir.join("socket");
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&bound, path)?;
+        Ok(listener)
+    });
+
+    std::fs::remove_file(&bound).unwrap_or_default();
+    std::fs::remove_dir(&dir).unwrap_or_default();
+    listener
+}
+
+#[cfg(unix)]
+struct UnixSocketListener {
+    listener: UnixListener,
+    path: PathBuf,
+    accepted: u64,
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl TransportListener for UnixSocketListener {
+    async fn accept(&mut self) -> io::Result<(Box<dyn TransportStream>, String)> {
+        let (stream, _) = self.listener.accept().await?;
+
+        // Unix socket peers are unnamed, so number them to tell them apart in logs.
+        self.accepted += 1;
+        let peer = format!("{}#{}", self.path.display(), self.accepted);
+        Ok((Box::new(stream), peer))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).unwrap_or_default();
+    }
+}
+
+#[cfg(unix)]
+fn unix_path(addr: &str) -> &str {
+    addr.strip_prefix(UNIX_SCHEME).unwrap_or(addr)
+}
+
+const LOOPBACK_BUFFER_SIZE: usize = 64 * 1024;
+
+// In-process transport: servers bind to any "host:port" name and clients reach them with
+// "ws://host:port", without touching the network.
+#[derive(Debug, Clone, Default)]
+pub struct Loopback {
+    listeners: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<DuplexStream>>>>,
+}
+
+impl Loopback {
+    pub fn new() -> Loopback {
+        Loopback::default()
+    }
+}
+
+#[async_trait]
+impl Transport for Loopback {
+    async fn connect(&self, url: &str) -> io::Result<Box<dyn TransportStream>> {
+        let addr = authority(url)?;
+        let (client, server) = tokio::io::duplex(LOOPBACK_BUFFER_SIZE);
+
+        let listeners = self.listeners.lock().unwrap();
+        match listeners.get(&addr).map(|listener| listener.send(server)) {
+            Some(Ok(())) => Ok(Box::new(client)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("Nothing is listening on {addr}"),
+            )),
+        }
+    }
+
+    async fn bind(&self, addr: &str) -> io::Result<Box<dyn TransportListener>> {
+        let mut listeners = self.listeners.lock().unwrap();
+        if let Some(listener) = listeners.get(addr) {
+            if !listener.is_closed() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{addr} is already bound"),
+                ));
+            }
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        listeners.insert(addr.to_string(), tx);
+
+        Ok(Box::new(LoopbackListener {
+            addr: addr.into(),
+            streams: rx,
+            accepted: 0,
+        }))
+    }
+}
+
+struct LoopbackListener {
+    addr: String,
+    streams: mpsc::UnboundedReceiver<DuplexStream>,
+    accepted: u64,
+}
+
+#[async_trait]
+impl TransportListener for LoopbackListener {
+    async fn accept(&mut self) -> io::Result<(Box<dyn TransportStream>, String)> {
+        let stream = self.streams.recv().await.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "Loopback transport dropped")
+        })?;
+
+        self.accepted += 1;
+        Ok((Box::new(stream), format!("{}#{}", self.addr, self.accepted)))
+    }
+}
+
+fn authority(url: &str) -> io::Result<String> {
+    let request = url
+        .into_client_request()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let uri = request.uri();
+
+    let host = uri
+        .host()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("No host in {url}")))?;
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("wss") => 443,
+        _ => 80,
+    });
+
+    Ok(format!("{host}:{port}"))
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use std::collections::HashMap;
+
+use tokio::sync::mpsc;
+use ws_protocol::{
+    Acl, AclEntry, ClientConfig, ConnectionState, Loopback, ServerConfig, WsConnection,
+};
+
+use common::{client_config, spawn_connection, start_server, wait_until, Echo, Recorder};
+
+// A server where discord may emit echo messages, proving its own secret.
+fn acl_config() -> ServerConfig {
+    ServerConfig {
+        secret: Some("shared".into()),
+        acl: Some(Acl {
+            identities: HashMap::from([(
+                "discord".to_string(),
+                AclEntry {
+                    secret: "discord's".into(),
+                    emits: vec!["echo".into()],
+                    handles: vec![],
+                },
+            )]),
+        }),
+        ..ServerConfig::default()
+    }
+}
+
+fn discord_config(secret: &str) -> ClientConfig {
+    let mut config = client_config();
+    config.identity = Some("discord".into());
+    config.secret = Some(secret.into());
+    config
+}
+
+#[tokio::test]
+async fn rejects_apis_the_acl_does_not_allow() {
+    let loopback = Loopback::new();
+    let server = start_server(&loopback, acl_config());
+
+    let mut config = discord_config("discord's");
+
+    let mut connection = WsConnection::with_config(config.clone());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+    wait_until(echo.connection_state(), ConnectionState::is_connected).await;
+
+    // It may emit, but not handle.
+    config.reconnect.max_attempts = Some(1);
+    let (tx, _messages) = mpsc::unbounded_channel();
+    let mut connection = WsConnection::with_config(config);
+    connection.handler(Recorder(tx));
+    let state = connection.connection_state();
+    let _handler = spawn_connection(&loopback, connection);
+
+    wait_until(state, |state| *state == ConnectionState::GaveUp).await;
+    assert!(server
+        .metrics
+        .render()
+        .contains("bjorn_ws_handshake_failures_total{reason=\"registration\"} 1\n"));
+}
+
+#[tokio::test]
+async fn rejects_identities_proving_the_shared_secret() {
+    let loopback = Loopback::new();
+    let server = start_server(&loopback, acl_config());
+
+    // Once the real discord is in, the server is up for the impostor to try.
+    let mut connection = WsConnection::with_config(discord_config("discord's"));
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+    wait_until(echo.connection_state(), ConnectionState::is_connected).await;
+
+    let mut config = discord_config("shared");
+    config.reconnect.max_attempts = Some(1);
+
+    let mut connection = WsConnection::with_config(config);
+    let impostor = connection.client::<Echo>();
+    let _impostor = spawn_connection(&loopback, connection);
+
+    wait_until(impostor.connection_state(), |state| {
+        *state == ConnectionState::GaveUp
+    })
+    .await;
+    assert!(server
+        .metrics
+        .render()
+        .contains("bjorn_ws_handshake_failures_total{reason=\"unauthorized\"} 1\n"));
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use ws_protocol::{Codec, DeliveryStatus, Loopback, ServerConfig, WsClient, WsConnection};
+
+use common::{
+    client_config, spawn_connection, start_server, wait_until, within, Echo, EchoMessage, Recorder,
+};
+
+fn connect(loopback: &Loopback, codec: Codec) -> (WsClient<Echo>, impl Sized) {
+    let mut config = client_config();
+    config.codec = codec;
+
+    let mut connection = WsConnection::with_config(config);
+    let echo = connection.client::<Echo>();
+    (echo, spawn_connection(loopback, connection))
+}
+
+// Long enough to need the 32-bit string length, with characters JSON has to escape.
+fn payload() -> String {
+    "\"Haldor\" \u{1f98c} at -1204.5, 33.25\n".repeat(2_000)
+}
+
+#[tokio::test]
+async fn transcodes_between_json_and_msgpack_peers() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    for (handler_codec, emitter_codec) in [
+        (Codec::MessagePack, Codec::Json),
+        (Codec::Json, Codec::MessagePack),
+        (Codec::MessagePack, Codec::MessagePack),
+    ] {
+        let (tx, mut messages) = tokio::sync::mpsc::unbounded_channel();
+        let mut config = client_config();
+        config.codec = handler_codec;
+        let mut connection = WsConnection::with_config(config);
+        connection.handler(Recorder(tx));
+        let handler = spawn_connection(&loopback, connection);
+
+        let (echo, emitter) = connect(&loopback, emitter_codec);
+        wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+
+        let status = within(echo.send_confirmed(EchoMessage::Say(payload()))).await;
+        assert_eq!(status.unwrap(), DeliveryStatus::Delivered(1));
+        assert_eq!(within(messages.recv()).await.unwrap(), payload());
+
+        let reply = within(echo.request(EchoMessage::Say("ping".into()))).await;
+        assert_eq!(reply.unwrap(), "echo: ping");
+
+        drop(emitter);
+        handler.cancel();
+        wait_until(echo.peer_schemas(), |schemas| schemas.is_empty()).await;
+    }
+}
@@ -0,0 +1,115 @@
+// Each test binary uses a different subset of these helpers.
+#![allow(dead_code)]
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use ws_protocol::{
+    Canceller, ClientApi, ClientApiHandler, ClientConfig, LimitStats, Loopback, Metrics,
+    QueueStats, ReconnectPolicy, ServerConfig, WsConnection, WsServer, WsTask,
+};
+
+pub const ADDR: &str = "bjorn:1";
+pub const URL: &str = "ws://bjorn:1";
+
+pub struct Echo;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum EchoMessage {
+    Say(String),
+}
+
+impl ClientApi for Echo {
+    type Message = EchoMessage;
+    type Reply = String;
+
+    fn id() -> &'static str {
+        "echo"
+    }
+}
+
+pub struct Recorder(pub mpsc::UnboundedSender<String>);
+
+#[async_trait]
+impl ClientApiHandler for Recorder {
+    type Api = Echo;
+    type Error = String;
+
+    async fn handle_message(&mut self, message: EchoMessage) -> Result<(), String> {
+        let EchoMessage::Say(text) = message;
+        self.0.send(text).map_err(|e| e.to_string())
+    }
+
+    async fn handle_request(&mut self, message: EchoMessage) -> Result<Option<String>, String> {
+        let EchoMessage::Say(text) = message;
+        Ok(Some(format!("echo: {text}")))
+    }
+}
+
+// A server on the loopback transport, running until this is dropped.
+pub struct Server {
+    pub metrics: Arc<Metrics>,
+    pub queue_stats: Arc<QueueStats>,
+    pub limit_stats: Arc<LimitStats>,
+    // The server's canceller can't be named outside ws_protocol.
+    shutdown: Box<dyn FnOnce()>,
+}
+
+impl Server {
+    pub fn shutdown(self) {
+        (self.shutdown)()
+    }
+}
+
+pub fn start_server(loopback: &Loopback, config: ServerConfig) -> Server {
+    let (runner, canceller) = WsServer::new(config);
+    let server = Server {
+        metrics: runner.metrics(),
+        queue_stats: runner.queue_stats(),
+        limit_stats: runner.limit_stats(),
+        shutdown: Box::new(move || canceller.shutdown()),
+    };
+    tokio::spawn(runner.with_transport(loopback.clone()).run(ADDR.into()));
+    server
+}
+
+pub fn spawn_connection(loopback: &Loopback, connection: WsConnection) -> Canceller {
+    let (runner, canceller) = connection.build();
+    tokio::spawn(runner.with_transport(loopback.clone()).run(URL.into()));
+    canceller
+}
+
+pub fn start_handler(loopback: &Loopback) -> (mpsc::UnboundedReceiver<String>, Canceller) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut connection = WsConnection::with_config(client_config());
+    connection.handler(Recorder(tx));
+    (rx, spawn_connection(loopback, connection))
+}
+
+pub fn client_config() -> ClientConfig {
+    ClientConfig {
+        reconnect: ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            jitter: 0.0,
+            ..ReconnectPolicy::default()
+        },
+        ..ClientConfig::default()
+    }
+}
+
+pub async fn within<F: Future>(future: F) -> F::Output {
+    tokio::time::timeout(Duration::from_secs(5), future)
+        .await
+        .expect("timed out")
+}
+
+pub async fn wait_until<T>(mut rx: watch::Receiver<T>, condition: impl Fn(&T) -> bool) {
+    within(async {
+        while !condition(&rx.borrow_and_update()) {
+            rx.changed().await.unwrap();
+        }
+    })
+    .await
+}
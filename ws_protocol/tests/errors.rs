@@ -0,0 +1,50 @@
+mod common;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use ws_protocol::{ClientApiHandler, Loopback, ServerConfig, WsConnection};
+
+use common::{
+    client_config, spawn_connection, start_server, wait_until, within, Echo, EchoMessage,
+};
+
+// Fails on anything it's told to say, and records what it failed with.
+struct Failing(mpsc::UnboundedSender<String>);
+
+#[async_trait]
+impl ClientApiHandler for Failing {
+    type Api = Echo;
+    type Error = String;
+
+    async fn handle_message(&mut self, message: EchoMessage) -> Result<(), String> {
+        let EchoMessage::Say(text) = message;
+        Err(format!("couldn't say {text}"))
+    }
+
+    fn on_error(&mut self, error: String) {
+        self.0.send(error).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn hands_handler_failures_to_on_error() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    let (tx, mut errors) = mpsc::unbounded_channel();
+    let mut connection = WsConnection::with_config(client_config());
+    connection.handler(Failing(tx));
+    let _handler = spawn_connection(&loopback, connection);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    // A failure doesn't stop the handler from getting the next message.
+    echo.send(EchoMessage::Say("hello".into()));
+    echo.send(EchoMessage::Say("again".into()));
+
+    assert_eq!(within(errors.recv()).await.unwrap(), "couldn't say hello");
+    assert_eq!(within(errors.recv()).await.unwrap(), "couldn't say again");
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use ws_protocol::{
+    ApiSpecifier, ClientApi, Handshake, Heartbeat, Loopback, Registration, ServerConfig, Transport,
+    WsConnection, PROTOCOL_VERSION,
+};
+
+use common::{client_config, spawn_connection, start_server, wait_until, within, Echo, URL};
+
+#[tokio::test]
+async fn drops_handlers_that_stop_answering_pings() {
+    let loopback = Loopback::new();
+    let _server = start_server(
+        &loopback,
+        ServerConfig {
+            heartbeat: Heartbeat {
+                interval: Duration::from_millis(20),
+                timeout: Duration::from_millis(100),
+            },
+            ..ServerConfig::default()
+        },
+    );
+
+    // Pongs are only sent while reading, so a handler that registers and then never reads
+    // again looks just like one that hung.
+    let _silent = within(async {
+        let stream = loop {
+            match loopback.connect(URL).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        let (mut ws, _) = tokio_tungstenite::client_async(URL, stream).await.unwrap();
+
+        ws.next().await.unwrap().unwrap();
+        let identification = Handshake::ClientIdentification {
+            apis: vec![Registration {
+                api_specifier: ApiSpecifier::Handles(Echo::id().into()),
+                schema: Echo::schema(),
+                replay: false,
+            }],
+            proof: None,
+            protocol_version: PROTOCOL_VERSION,
+            identity: None,
+            codecs: vec![],
+        };
+        ws.send(identification.into()).await.unwrap();
+        ws.next().await.unwrap().unwrap();
+        ws
+    })
+    .await;
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+    wait_until(echo.peer_schemas(), |schemas| schemas.is_empty()).await;
+}
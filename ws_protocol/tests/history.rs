@@ -0,0 +1,160 @@
+mod common;
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use ws_protocol::{
+    ClientApi, ClientApiHandler, DeliveryStatus, Envelope, HistoryPolicy, Loopback, ServerConfig,
+    WsClient, WsConnection,
+};
+
+use common::{client_config, spawn_connection, start_server, wait_until, within};
+
+struct Status;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum StatusMessage {
+    Started(String),
+    Stopped,
+    Joined(String),
+}
+
+impl ClientApi for Status {
+    type Message = StatusMessage;
+    type Reply = ();
+
+    fn id() -> &'static str {
+        "status"
+    }
+}
+
+struct Watcher {
+    replay: bool,
+    seen: mpsc::UnboundedSender<(bool, StatusMessage)>,
+}
+
+#[async_trait]
+impl ClientApiHandler for Watcher {
+    type Api = Status;
+    type Error = String;
+
+    async fn handle_message(&mut self, _message: StatusMessage) -> Result<(), String> {
+        Err("Watcher needs the envelope".into())
+    }
+
+    async fn handle_enveloped_message(
+        &mut self,
+        envelope: Envelope,
+        message: StatusMessage,
+    ) -> Result<(), String> {
+        self.seen
+            .send((envelope.replayed, message))
+            .map_err(|e| e.to_string())
+    }
+
+    fn replay_history(&self) -> bool {
+        self.replay
+    }
+}
+
+fn start(policy: HistoryPolicy) -> (Loopback, WsClient<Status>, impl Sized) {
+    let loopback = Loopback::new();
+    let server = start_server(
+        &loopback,
+        ServerConfig {
+            history: HashMap::from([("status".to_string(), policy)]),
+            ..ServerConfig::default()
+        },
+    );
+
+    let mut connection = WsConnection::with_config(client_config());
+    let status = connection.client::<Status>();
+    let emitter = spawn_connection(&loopback, connection);
+
+    (loopback, status, (server, emitter))
+}
+
+async fn emit(status: &WsClient<Status>, messages: Vec<StatusMessage>) {
+    for message in messages {
+        let delivered = within(status.send_confirmed(message)).await.unwrap();
+        assert_ne!(delivered, DeliveryStatus::Stored);
+    }
+}
+
+fn watch(
+    loopback: &Loopback,
+    replay: bool,
+) -> (mpsc::UnboundedReceiver<(bool, StatusMessage)>, impl Sized) {
+    let (seen, rx) = mpsc::unbounded_channel();
+    let mut connection = WsConnection::with_config(client_config());
+    connection.handler(Watcher { replay, seen });
+    (rx, spawn_connection(loopback, connection))
+}
+
+#[tokio::test]
+async fn replays_the_latest_message_of_each_variant() {
+    let (loopback, status, _running) = start(HistoryPolicy::LatestPerKey);
+    emit(
+        &status,
+        vec![
+            StatusMessage::Started("1234".into()),
+            StatusMessage::Joined("Bjorn".into()),
+            StatusMessage::Stopped,
+            StatusMessage::Started("5678".into()),
+            StatusMessage::Joined("Haldor".into()),
+        ],
+    )
+    .await;
+
+    let (mut seen, _watcher) = watch(&loopback, true);
+    for expected in [
+        StatusMessage::Stopped,
+        StatusMessage::Started("5678".into()),
+        StatusMessage::Joined("Haldor".into()),
+    ] {
+        assert_eq!(within(seen.recv()).await.unwrap(), (true, expected));
+    }
+
+    wait_until(status.peer_schemas(), |schemas| schemas.len() == 1).await;
+    emit(&status, vec![StatusMessage::Stopped]).await;
+    assert_eq!(
+        within(seen.recv()).await.unwrap(),
+        (false, StatusMessage::Stopped)
+    );
+}
+
+#[tokio::test]
+async fn replays_only_the_last_messages_and_only_on_request() {
+    let (loopback, status, _running) = start(HistoryPolicy::Last(2));
+    emit(
+        &status,
+        vec![
+            StatusMessage::Joined("Bjorn".into()),
+            StatusMessage::Joined("Haldor".into()),
+            StatusMessage::Joined("Hugin".into()),
+        ],
+    )
+    .await;
+
+    let (mut live, _live) = watch(&loopback, false);
+    let (mut replayed, _replayed) = watch(&loopback, true);
+    for expected in ["Haldor", "Hugin"] {
+        assert_eq!(
+            within(replayed.recv()).await.unwrap(),
+            (true, StatusMessage::Joined(expected.into()))
+        );
+    }
+
+    wait_until(status.peer_schemas(), |schemas| schemas.len() == 2).await;
+    emit(&status, vec![StatusMessage::Stopped]).await;
+    assert_eq!(
+        within(live.recv()).await.unwrap(),
+        (false, StatusMessage::Stopped)
+    );
+    assert_eq!(
+        within(replayed.recv()).await.unwrap(),
+        (false, StatusMessage::Stopped)
+    );
+}
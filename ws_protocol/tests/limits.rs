@@ -0,0 +1,158 @@
+mod common;
+
+use std::time::Duration;
+
+use ws_protocol::{
+    Codec, DeliveryStatus, LimitPolicy, Limits, Loopback, Rate, ServerConfig, WsConnection,
+};
+
+use common::{
+    client_config, spawn_connection, start_handler, start_server, wait_until, within, Echo,
+    EchoMessage,
+};
+
+#[tokio::test]
+async fn disconnects_a_client_over_its_rate_limit() {
+    let loopback = Loopback::new();
+    let server = start_server(
+        &loopback,
+        ServerConfig {
+            limits: Limits {
+                connection_rate: Some(Rate {
+                    burst: 2,
+                    per_second: 0.001,
+                }),
+                policy: LimitPolicy::Disconnect,
+                ..Limits::default()
+            },
+            ..ServerConfig::default()
+        },
+    );
+    let (mut messages, _handler) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+
+    for text in ["one", "two", "three"] {
+        echo.send(EchoMessage::Say(text.into()));
+    }
+
+    assert_eq!(within(messages.recv()).await.unwrap(), "one");
+    assert_eq!(within(messages.recv()).await.unwrap(), "two");
+    within(async {
+        while server.limit_stats.snapshot().disconnected == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+
+    let stats = server.limit_stats.snapshot();
+    assert_eq!(stats.rate_limited, 1);
+    assert_eq!(stats.disconnected, 1);
+    assert!(messages.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn tells_the_sender_about_messages_dropped_by_a_limit() {
+    let loopback = Loopback::new();
+    let server = start_server(
+        &loopback,
+        ServerConfig {
+            limits: Limits {
+                max_message_size: Some(1024),
+                connection_rate: Some(Rate {
+                    burst: 2,
+                    per_second: 0.001,
+                }),
+                policy: LimitPolicy::Drop,
+                ..Limits::default()
+            },
+            ..ServerConfig::default()
+        },
+    );
+    let (mut messages, _handler) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+
+    let say = |text: &str| echo.send_confirmed(EchoMessage::Say(text.into()));
+
+    assert_eq!(
+        within(say(&"long ".repeat(1000))).await.unwrap(),
+        DeliveryStatus::TooLarge
+    );
+    assert_eq!(
+        within(say("one")).await.unwrap(),
+        DeliveryStatus::Delivered(1)
+    );
+    assert_eq!(
+        within(say("two")).await.unwrap(),
+        DeliveryStatus::Delivered(1)
+    );
+    assert_eq!(
+        within(say("three")).await.unwrap(),
+        DeliveryStatus::RateLimited
+    );
+
+    assert_eq!(within(messages.recv()).await.unwrap(), "one");
+    assert_eq!(within(messages.recv()).await.unwrap(), "two");
+
+    let stats = server.limit_stats.snapshot();
+    assert_eq!(stats.oversized, 1);
+    assert_eq!(stats.rate_limited, 1);
+    assert_eq!(stats.disconnected, 0);
+}
+
+fn dropping_oversized(max_message_size: usize) -> ServerConfig {
+    ServerConfig {
+        limits: Limits {
+            max_message_size: Some(max_message_size),
+            policy: LimitPolicy::Drop,
+            ..Limits::default()
+        },
+        ..ServerConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn acknowledges_oversized_messagepack_frames() {
+    let loopback = Loopback::new();
+    let server = start_server(&loopback, dropping_oversized(1024));
+    let (_messages, _handler) = start_handler(&loopback);
+
+    let mut config = client_config();
+    config.codec = Codec::MessagePack;
+    let mut connection = WsConnection::with_config(config);
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+
+    let status = echo.send_confirmed(EchoMessage::Say("long ".repeat(1000)));
+    assert_eq!(within(status).await.unwrap(), DeliveryStatus::TooLarge);
+    assert_eq!(server.limit_stats.snapshot().disconnected, 0);
+}
+
+#[tokio::test]
+async fn disconnects_a_client_far_over_the_size_limit_even_when_dropping() {
+    let loopback = Loopback::new();
+    let server = start_server(&loopback, dropping_oversized(1024));
+    let (_messages, _handler) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+
+    echo.send(EchoMessage::Say("x".repeat(1024 * 1024)));
+    within(async {
+        while server.limit_stats.snapshot().disconnected == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert_eq!(server.limit_stats.snapshot().oversized, 1);
+}
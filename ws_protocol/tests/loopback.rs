@@ -0,0 +1,461 @@
+mod common;
+
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    tungstenite::{self, protocol::frame::coding::CloseCode},
+    WebSocketStream,
+};
+use ws_protocol::{
+    ApiSpecifier, ClientApi, ClientApiHandler, ConnectionState, DeliveryStatus, Envelope,
+    Handshake, InvalidInstance, Loopback, ObserverIdentification, Registration, ServerConfig,
+    Transport, TransportStream, WsClient, WsConnection, PROTOCOL_VERSION,
+};
+
+use common::{
+    client_config, spawn_connection, start_handler, start_server, wait_until, within, Echo,
+    EchoMessage, URL,
+};
+
+struct Relay {
+    instance: Option<&'static str>,
+    envelopes: mpsc::UnboundedSender<Envelope>,
+    forward_to: Option<WsClient<Echo>>,
+}
+
+#[async_trait]
+impl ClientApiHandler for Relay {
+    type Api = Echo;
+    type Error = String;
+
+    async fn handle_message(&mut self, _message: EchoMessage) -> Result<(), String> {
+        Err("Relay needs the envelope".into())
+    }
+
+    async fn handle_enveloped_message(
+        &mut self,
+        envelope: Envelope,
+        message: EchoMessage,
+    ) -> Result<(), String> {
+        self.envelopes.send(envelope).unwrap();
+        if let Some(forward_to) = &self.forward_to {
+            forward_to.send(message);
+        }
+        Ok(())
+    }
+
+    fn instance(&self) -> Option<&str> {
+        self.instance
+    }
+}
+
+#[tokio::test]
+async fn routes_messages_to_every_handler() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+    let (mut first, _first) = start_handler(&loopback);
+    let (mut second, _second) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _canceller = spawn_connection(&loopback, connection);
+
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 2).await;
+
+    let status = within(echo.send_confirmed(EchoMessage::Say("hello".into()))).await;
+    assert_eq!(status.unwrap(), DeliveryStatus::Delivered(2));
+    assert_eq!(within(first.recv()).await.unwrap(), "hello");
+    assert_eq!(within(second.recv()).await.unwrap(), "hello");
+}
+
+#[tokio::test]
+async fn replies_to_requests() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+    let (_messages, _handler) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _canceller = spawn_connection(&loopback, connection);
+
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    let reply = within(echo.request(EchoMessage::Say("ping".into()))).await;
+    assert_eq!(reply.unwrap(), "echo: ping");
+}
+
+#[tokio::test]
+async fn rejects_clients_with_the_wrong_secret() {
+    let loopback = Loopback::new();
+    let _server = start_server(
+        &loopback,
+        ServerConfig {
+            secret: Some("right".into()),
+            ..ServerConfig::default()
+        },
+    );
+
+    let mut config = client_config();
+    config.secret = Some("wrong".into());
+    config.reconnect.max_attempts = Some(1);
+
+    let mut connection = WsConnection::with_config(config);
+    let echo = connection.client::<Echo>();
+    let _canceller = spawn_connection(&loopback, connection);
+
+    wait_until(echo.connection_state(), |state| {
+        *state == ConnectionState::GaveUp
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn cleans_up_after_a_handler_disconnects() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+    let (_messages, handler) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _canceller = spawn_connection(&loopback, connection);
+
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+
+    handler.cancel();
+    wait_until(echo.peer_schemas(), |schemas| schemas.is_empty()).await;
+
+    let status = within(echo.send_confirmed(EchoMessage::Say("anyone?".into()))).await;
+    assert_eq!(status.unwrap(), DeliveryStatus::NoRoute);
+}
+
+#[tokio::test]
+async fn stamps_envelopes_and_carries_traces_through_handlers() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    let (envelopes, mut relayed) = mpsc::unbounded_channel();
+    let mut connection = WsConnection::with_config(client_config());
+    connection.handler(Relay {
+        instance: Some("relayed"),
+        envelopes,
+        forward_to: None,
+    });
+    let _last = spawn_connection(&loopback, connection);
+
+    let (envelopes, mut received) = mpsc::unbounded_channel();
+    let mut connection = WsConnection::with_config(client_config());
+    let forward_to = connection.instance_client::<Echo>(Some("relayed")).unwrap();
+    let forwarded = forward_to.peer_schemas();
+    connection.handler(Relay {
+        instance: None,
+        envelopes,
+        forward_to: Some(forward_to),
+    });
+    let _relay = spawn_connection(&loopback, connection);
+
+    let mut config = client_config();
+    config.identity = Some("bot".into());
+    let mut connection = WsConnection::with_config(config);
+    let echo = connection.client::<Echo>();
+    let _canceller = spawn_connection(&loopback, connection);
+
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+    wait_until(forwarded, |schemas| !schemas.is_empty()).await;
+
+    echo.send(EchoMessage::Say("hello".into()));
+
+    let first = within(received.recv()).await.unwrap();
+    assert_eq!(first.sender.as_deref(), Some("bot"));
+    assert_eq!(first.trace_id, None);
+
+    let second = within(relayed.recv()).await.unwrap();
+    assert_ne!(second.id, first.id);
+    assert_ne!(second.sender, first.sender);
+    assert_eq!(second.trace_id, Some(first.id));
+    assert!(second.timestamp >= first.timestamp);
+}
+
+type RawClient = WebSocketStream<Box<dyn TransportStream>>;
+
+async fn connect_ws(loopback: &Loopback) -> RawClient {
+    let stream = loop {
+        match loopback.connect(URL).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::task::yield_now().await,
+        }
+    };
+
+    tokio_tungstenite::client_async(URL, stream)
+        .await
+        .unwrap()
+        .0
+}
+
+async fn connect_raw(loopback: &Loopback, identification: Handshake) -> (RawClient, Handshake) {
+    within(async {
+        let mut ws = connect_ws(loopback).await;
+
+        ws.next().await.unwrap().unwrap();
+        ws.send(identification.into()).await.unwrap();
+        let response = Handshake::try_from(ws.next().await.unwrap().unwrap()).unwrap();
+
+        (ws, response)
+    })
+    .await
+}
+
+// Answers the server's challenge with the secret, if there is one.
+async fn observe(loopback: &Loopback, secret: Option<&str>) -> Handshake {
+    within(async {
+        let mut ws = connect_ws(loopback).await;
+
+        let challenge = match Handshake::try_from(ws.next().await.unwrap().unwrap()) {
+            Ok(Handshake::ServerIdentification(identification)) => identification.challenge,
+            response => panic!("Expected the server to identify itself, got {response:?}"),
+        };
+        let identification = Handshake::Web(ObserverIdentification {
+            subscriptions: vec!["echo".into()],
+            proof: secret.map(|secret| ws_protocol_version::prove(secret, &challenge)),
+            protocol_version: PROTOCOL_VERSION,
+        });
+        ws.send(identification.into()).await.unwrap();
+
+        Handshake::try_from(ws.next().await.unwrap().unwrap()).unwrap()
+    })
+    .await
+}
+
+fn identify_as(api_specifier: ApiSpecifier) -> Handshake {
+    Handshake::ClientIdentification {
+        apis: vec![Registration {
+            api_specifier,
+            schema: Echo::schema(),
+            replay: false,
+        }],
+        proof: None,
+        protocol_version: PROTOCOL_VERSION,
+        identity: None,
+        codecs: vec![],
+    }
+}
+
+// Sends a request and returns the first message that comes back.
+async fn raw_request(ws: &mut RawClient, correlation_id: u64, text: &str) -> serde_json::Value {
+    let request = serde_json::json!({
+        "target": "echo",
+        "content": serde_json::to_string(&EchoMessage::Say(text.into())).unwrap(),
+        "correlation_id": correlation_id,
+    });
+    ws.send(tungstenite::Message::Text(request.to_string()))
+        .await
+        .unwrap();
+
+    next_message(ws).await
+}
+
+// Skips control frames.
+async fn next_message(ws: &mut RawClient) -> serde_json::Value {
+    within(async {
+        loop {
+            let frame = ws.next().await.unwrap().unwrap();
+            let message: serde_json::Value =
+                serde_json::from_str(frame.to_text().unwrap()).unwrap();
+            if message.get("target").is_some() {
+                return message;
+            }
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn keeps_observers_out_of_a_server_with_a_secret() {
+    let loopback = Loopback::new();
+    let _server = start_server(
+        &loopback,
+        ServerConfig {
+            secret: Some("clients only".into()),
+            ..ServerConfig::default()
+        },
+    );
+
+    assert!(matches!(
+        observe(&loopback, None).await,
+        Handshake::Rejected(_)
+    ));
+}
+
+#[tokio::test]
+async fn lets_observers_in_anonymously_when_allowed() {
+    let loopback = Loopback::new();
+    let _server = start_server(
+        &loopback,
+        ServerConfig {
+            secret: Some("clients only".into()),
+            anonymous_observers: true,
+            ..ServerConfig::default()
+        },
+    );
+
+    assert!(matches!(
+        observe(&loopback, None).await,
+        Handshake::Accepted
+    ));
+}
+
+#[tokio::test]
+async fn lets_observers_in_with_the_observer_secret() {
+    let loopback = Loopback::new();
+    let _server = start_server(
+        &loopback,
+        ServerConfig {
+            secret: Some("clients only".into()),
+            observer_secret: Some("dashboard".into()),
+            ..ServerConfig::default()
+        },
+    );
+
+    assert!(matches!(
+        observe(&loopback, Some("clients only")).await,
+        Handshake::Rejected(_)
+    ));
+    assert!(matches!(
+        observe(&loopback, Some("dashboard")).await,
+        Handshake::Accepted
+    ));
+}
+
+#[tokio::test]
+async fn rejects_registrations_with_invalid_instance_names() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    for target in ["echo@", "echo@two words", "echo@relayed@again", "@relayed"] {
+        let identification = identify_as(ApiSpecifier::Handles(target.into()));
+        let (_, response) = connect_raw(&loopback, identification).await;
+        assert!(matches!(response, Handshake::Rejected(_)), "{target}");
+    }
+
+    let identification = identify_as(ApiSpecifier::Handles("echo@relayed".into()));
+    let (_, response) = connect_raw(&loopback, identification).await;
+    assert!(matches!(response, Handshake::Accepted));
+}
+
+#[test]
+fn refuses_clients_for_invalid_instance_names() {
+    let mut connection = WsConnection::with_config(client_config());
+    for instance in ["", "two words", "relayed@again"] {
+        let error = connection.instance_client::<Echo>(Some(instance)).err();
+        assert_eq!(error, Some(InvalidInstance(instance.into())), "{instance}");
+    }
+    assert!(connection.instance_client::<Echo>(Some("relayed")).is_ok());
+
+    assert!(WsClient::<Echo>::with_config_for_instance(client_config(), Some("@")).is_err());
+}
+
+#[tokio::test]
+async fn sends_replies_only_to_the_requester() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+    let (_messages, _handler) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _canceller = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    let (mut raw, accepted) =
+        connect_raw(&loopback, identify_as(ApiSpecifier::Emits("echo".into()))).await;
+    assert!(matches!(accepted, Handshake::Accepted));
+    let reply = raw_request(&mut raw, 1, "first").await;
+    assert_eq!(reply["correlation_id"], 1);
+
+    let reply = within(echo.request(EchoMessage::Say("hello".into()))).await;
+    assert_eq!(reply.unwrap(), "echo: hello");
+
+    // Had the reply above gone to every emitter, it'd be queued ahead of this one.
+    let reply = raw_request(&mut raw, 2, "second").await;
+    assert_eq!(reply["correlation_id"], 2);
+}
+
+#[tokio::test]
+async fn keeps_server_fields_out_of_clients_hands() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    let (mut handler, _) =
+        connect_raw(&loopback, identify_as(ApiSpecifier::Handles("echo".into()))).await;
+
+    // Seeing the handler means it's registered.
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _canceller = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    let (mut emitter, _) =
+        connect_raw(&loopback, identify_as(ApiSpecifier::Emits("echo".into()))).await;
+    let message = serde_json::json!({
+        "target": "echo",
+        "content": serde_json::to_string(&EchoMessage::Say("hello".into())).unwrap(),
+        "delivery_id": 5,
+        "replayed": true,
+        "sender": "someone else",
+    });
+    emitter
+        .send(tungstenite::Message::Text(message.to_string()))
+        .await
+        .unwrap();
+
+    let received = next_message(&mut handler).await;
+    assert_eq!(received["content"], message["content"]);
+    assert_eq!(received.get("delivery_id"), None);
+    assert_eq!(received.get("replayed"), None);
+    assert_ne!(received["sender"], "someone else");
+}
+
+#[tokio::test]
+async fn flushes_queued_frames_before_shutting_down() {
+    let loopback = Loopback::new();
+    let server = start_server(&loopback, ServerConfig::default());
+
+    let (mut handler, _) =
+        connect_raw(&loopback, identify_as(ApiSpecifier::Handles("echo".into()))).await;
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    // More than the loopback buffers, so most of it is still queued on the server while the
+    // handler isn't reading.
+    const SENT: usize = 20;
+    for index in 0..SENT {
+        let text = format!("{index}:{}", "x".repeat(16 * 1024));
+        let status = within(echo.send_confirmed(EchoMessage::Say(text))).await;
+        assert_eq!(status.unwrap(), DeliveryStatus::Delivered(1));
+    }
+
+    assert!(server.queue_stats.snapshot().depth > 0);
+    server.shutdown();
+
+    let mut received = 0;
+    let close = within(async {
+        loop {
+            match handler.next().await.unwrap().unwrap() {
+                tungstenite::Message::Close(close) => break close,
+                frame => {
+                    let message: serde_json::Value =
+                        serde_json::from_str(frame.to_text().unwrap()).unwrap();
+                    if message.get("target").is_some() {
+                        received += 1;
+                    }
+                }
+            }
+        }
+    })
+    .await;
+
+    assert_eq!(received, SENT);
+    assert_eq!(close.unwrap().code, CloseCode::Normal);
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use ws_protocol::{ConnectionState, DeliveryStatus, Loopback, ServerConfig, WsConnection};
+
+use common::{
+    client_config, spawn_connection, start_handler, start_server, wait_until, within, Echo,
+    EchoMessage,
+};
+
+async fn scrape(addr: &str, method: &str, path: &str) -> String {
+    within(async {
+        let mut stream = loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+
+        stream
+            .write_all(format!("{method} {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    })
+    .await
+}
+
+#[tokio::test]
+async fn counts_clients_and_what_happens_to_their_messages() {
+    let loopback = Loopback::new();
+    let server = start_server(&loopback, ServerConfig::default());
+    let metrics = &server.metrics;
+
+    let (_messages, handler) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+    let status = within(echo.send_confirmed(EchoMessage::Say("hello".into()))).await;
+    assert_eq!(status.unwrap(), DeliveryStatus::Delivered(1));
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("bjorn_ws_clients{role=\"handles\",target=\"echo\"} 1\n"));
+    assert!(rendered.contains("bjorn_ws_clients{role=\"emits\",target=\"echo\"} 1\n"));
+    assert!(rendered.contains("bjorn_ws_messages_routed_total{target=\"echo\"} 1\n"));
+
+    handler.cancel();
+    wait_until(echo.peer_schemas(), |schemas| schemas.is_empty()).await;
+    let status = within(echo.send_confirmed(EchoMessage::Say("anyone?".into()))).await;
+    assert_eq!(status.unwrap(), DeliveryStatus::NoRoute);
+
+    // The gauge stays at zero rather than disappearing, so it can be alerted on.
+    let rendered = metrics.render();
+    assert!(rendered.contains("bjorn_ws_clients{role=\"handles\",target=\"echo\"} 0\n"));
+    assert!(rendered
+        .contains("bjorn_ws_messages_dropped_total{target=\"echo\",reason=\"no_route\"} 1\n"));
+    assert!(rendered.contains("bjorn_ws_reconnects_total{role=\"handles\",target=\"echo\"} 0\n"));
+
+    let (_messages, _handler) = start_handler(&loopback);
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("bjorn_ws_clients{role=\"handles\",target=\"echo\"} 1\n"));
+    assert!(rendered.contains("bjorn_ws_reconnects_total{role=\"handles\",target=\"echo\"} 1\n"));
+}
+
+#[tokio::test]
+async fn serves_metrics_over_http() {
+    let metrics_addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+
+    let loopback = Loopback::new();
+    let _server = start_server(
+        &loopback,
+        ServerConfig {
+            secret: Some("right".into()),
+            metrics_address: Some(metrics_addr.clone()),
+            ..ServerConfig::default()
+        },
+    );
+
+    // Both are listening once the endpoint answers, with every family there from the start.
+    let response = scrape(&metrics_addr, "GET", "/metrics").await;
+    assert!(response.contains("bjorn_ws_handshake_failures_total{reason=\"unauthorized\"} 0\n"));
+    for family in [
+        "clients",
+        "reconnects_total",
+        "messages_routed_total",
+        "messages_dropped_total",
+    ] {
+        assert!(
+            response.contains(&format!("# TYPE bjorn_ws_{family} ")),
+            "{family}"
+        );
+        assert!(
+            !response.contains(&format!("\nbjorn_ws_{family}")),
+            "{family}"
+        );
+    }
+
+    let mut config = client_config();
+    config.secret = Some("wrong".into());
+    config.reconnect.max_attempts = Some(1);
+
+    let mut connection = WsConnection::with_config(config);
+    let echo = connection.client::<Echo>();
+    let _canceller = spawn_connection(&loopback, connection);
+
+    wait_until(echo.connection_state(), |state| {
+        *state == ConnectionState::GaveUp
+    })
+    .await;
+
+    let response = scrape(&metrics_addr, "GET", "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("# TYPE bjorn_ws_handshake_failures_total counter\n"));
+    assert!(response.contains("bjorn_ws_handshake_failures_total{reason=\"unauthorized\"} 1\n"));
+    assert!(response.contains("bjorn_ws_queue_depth 0\n"));
+
+    let response = scrape(&metrics_addr, "GET", "/").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let response = scrape(&metrics_addr, "POST", "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+}
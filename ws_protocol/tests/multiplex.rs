@@ -0,0 +1,168 @@
+mod common;
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use ws_protocol::{
+    ClientApi, ClientApiHandler, ConnectionState, Delivery, Heartbeat, InboxPolicy, Loopback,
+    ServerConfig, WsConnection,
+};
+
+use common::{
+    client_config, spawn_connection, start_server, wait_until, within, Echo, EchoMessage, Recorder,
+};
+
+struct Shout;
+
+#[derive(Debug, Serialize, Deserialize)]
+enum ShoutMessage {
+    Shout(String),
+}
+
+impl ClientApi for Shout {
+    type Message = ShoutMessage;
+    type Reply = ();
+
+    fn id() -> &'static str {
+        "shout"
+    }
+}
+
+struct ShoutRecorder(mpsc::UnboundedSender<String>);
+
+#[async_trait]
+impl ClientApiHandler for ShoutRecorder {
+    type Api = Shout;
+    type Error = String;
+
+    async fn handle_message(&mut self, message: ShoutMessage) -> Result<(), String> {
+        let ShoutMessage::Shout(text) = message;
+        self.0.send(text).map_err(|e| e.to_string())
+    }
+}
+
+// Never gets past its first message.
+struct Stalled;
+
+#[async_trait]
+impl ClientApiHandler for Stalled {
+    type Api = Echo;
+    type Error = String;
+
+    async fn handle_message(&mut self, _: EchoMessage) -> Result<(), String> {
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn carries_several_apis_over_one_connection() {
+    let loopback = Loopback::new();
+    let server = start_server(&loopback, ServerConfig::default());
+
+    // Each connection emits one API and handles the other.
+    let (shouts_tx, mut shouts) = mpsc::unbounded_channel();
+    let mut first = WsConnection::with_config(client_config());
+    let echo = first.client::<Echo>();
+    first.handler(ShoutRecorder(shouts_tx));
+    let _first = spawn_connection(&loopback, first);
+
+    let (echoes_tx, mut echoes) = mpsc::unbounded_channel();
+    let mut second = WsConnection::with_config(client_config());
+    let shout = second.client::<Shout>();
+    second.handler(Recorder(echoes_tx));
+    let _second = spawn_connection(&loopback, second);
+
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+    wait_until(shout.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    let sent = echo.try_send(EchoMessage::Say("hello".into())).unwrap();
+    assert_eq!(sent, Delivery::Sent);
+    let sent = shout.try_send(ShoutMessage::Shout("HELLO".into())).unwrap();
+    assert_eq!(sent, Delivery::Sent);
+
+    assert_eq!(within(echoes.recv()).await.unwrap(), "hello");
+    assert_eq!(within(shouts.recv()).await.unwrap(), "HELLO");
+
+    let metrics = server.metrics.render();
+    for api in [
+        "role=\"emits\",target=\"echo\"",
+        "role=\"handles\",target=\"echo\"",
+        "role=\"emits\",target=\"shout\"",
+        "role=\"handles\",target=\"shout\"",
+    ] {
+        assert!(
+            metrics.contains(&format!("bjorn_ws_clients{{{api}}} 1\n")),
+            "{metrics}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn keeps_reading_past_a_stalled_handler() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    let (shouts_tx, mut shouts) = mpsc::unbounded_channel();
+    let mut config = client_config();
+    config.inbox_capacity = 1;
+    config.heartbeat = Heartbeat {
+        interval: Duration::from_millis(20),
+        timeout: Duration::from_millis(100),
+    };
+    let mut stalled = WsConnection::with_config(config);
+    stalled.handler(Stalled);
+    stalled.handler(ShoutRecorder(shouts_tx));
+    let mut state = stalled.connection_state();
+    let _stalled = spawn_connection(&loopback, stalled);
+
+    let mut emitter = WsConnection::with_config(client_config());
+    let echo = emitter.client::<Echo>();
+    let shout = emitter.client::<Shout>();
+    let _emitter = spawn_connection(&loopback, emitter);
+
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+    wait_until(shout.peer_schemas(), |schemas| !schemas.is_empty()).await;
+    wait_until(state.clone(), |state| *state == ConnectionState::Connected).await;
+    state.borrow_and_update();
+
+    for index in 0..20 {
+        echo.try_send(EchoMessage::Say(index.to_string())).unwrap();
+    }
+    shout.try_send(ShoutMessage::Shout("HELLO".into())).unwrap();
+    assert_eq!(within(shouts.recv()).await.unwrap(), "HELLO");
+
+    // Long enough for a heartbeat timeout, had pongs been stuck behind the full inbox.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!state.has_changed().unwrap());
+}
+
+#[tokio::test]
+async fn drops_the_connection_for_a_stalled_handler_when_configured_to() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    // Connected first, so the server is known to be listening before the stalled handler
+    // connects, and that can't back off early.
+    let mut emitter = WsConnection::with_config(client_config());
+    let echo = emitter.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, emitter);
+    wait_until(echo.connection_state(), ConnectionState::is_connected).await;
+
+    let mut config = client_config();
+    config.inbox_capacity = 1;
+    config.inbox_policy = InboxPolicy::Disconnect;
+    // Backing off long enough that the state can't be missed.
+    config.reconnect.initial_delay = Duration::from_secs(60);
+    let mut stalled = WsConnection::with_config(config);
+    stalled.handler(Stalled);
+    let state = stalled.connection_state();
+    let _stalled = spawn_connection(&loopback, stalled);
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    for index in 0..20 {
+        echo.try_send(EchoMessage::Say(index.to_string())).unwrap();
+    }
+    wait_until(state, |state| matches!(state, ConnectionState::Backoff(_))).await;
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use ws_protocol::{
+    ApiSpecifier, ClientApi, ConnectionState, Handshake, Loopback, Registration, ServerConfig,
+    Transport, WsConnection, PROTOCOL_VERSION,
+};
+
+use common::{
+    client_config, spawn_connection, start_handler, start_server, wait_until, within, Echo,
+    EchoMessage, URL,
+};
+
+// Echo as a newer build would see it, with a schema the running handlers don't speak.
+struct EchoV2;
+
+impl ClientApi for EchoV2 {
+    type Message = EchoMessage;
+    type Reply = String;
+
+    fn id() -> &'static str {
+        Echo::id()
+    }
+
+    fn schema_version() -> u32 {
+        2
+    }
+}
+
+// Identifies itself with the given handshake and returns the server's answer.
+async fn handshake(loopback: &Loopback, identification: impl Into<Message>) -> Handshake {
+    within(async {
+        let stream = loop {
+            match loopback.connect(URL).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        let (mut ws, _) = tokio_tungstenite::client_async(URL, stream).await.unwrap();
+
+        ws.next().await.unwrap().unwrap();
+        ws.send(identification.into()).await.unwrap();
+        Handshake::try_from(ws.next().await.unwrap().unwrap()).unwrap()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn rejects_clients_with_an_incompatible_schema() {
+    let loopback = Loopback::new();
+    let server = start_server(&loopback, ServerConfig::default());
+    let (_messages, _handler) = start_handler(&loopback);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _current = spawn_connection(&loopback, connection);
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    let mut config = client_config();
+    config.reconnect.max_attempts = Some(1);
+    let mut connection = WsConnection::with_config(config);
+    let newer = connection.client::<EchoV2>();
+    let _newer = spawn_connection(&loopback, connection);
+
+    wait_until(newer.connection_state(), |state| {
+        *state == ConnectionState::GaveUp
+    })
+    .await;
+    assert!(server
+        .metrics
+        .render()
+        .contains("bjorn_ws_handshake_failures_total{reason=\"registration\"} 1\n"));
+}
+
+#[tokio::test]
+async fn rejects_clients_speaking_another_protocol_version() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    let identification = Handshake::ClientIdentification {
+        apis: vec![Registration {
+            api_specifier: ApiSpecifier::Emits(Echo::id().into()),
+            schema: Echo::schema(),
+            replay: false,
+        }],
+        proof: None,
+        protocol_version: PROTOCOL_VERSION + 1,
+        identity: None,
+        codecs: vec![],
+    };
+
+    match handshake(&loopback, identification).await {
+        Handshake::Rejected(reason) => {
+            assert!(
+                reason.starts_with("Unsupported protocol version"),
+                "{reason}"
+            )
+        }
+        response => panic!("Expected a rejection, got {response:?}"),
+    }
+}
+
+#[tokio::test]
+async fn tells_clients_from_before_multiplexing_their_version_is_unsupported() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+
+    // How a version 2 client, which registered a single API, identified itself.
+    let identification = r#"{"ClientIdentification":{"api_specifier":{"Emits":"echo"},"proof":null,"protocol_version":2,"schema":null}}"#;
+
+    match handshake(&loopback, identification.to_string()).await {
+        Handshake::Rejected(reason) => {
+            assert!(
+                reason.starts_with("Unsupported protocol version 2"),
+                "{reason}"
+            )
+        }
+        response => panic!("Expected a rejection, got {response:?}"),
+    }
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use ws_protocol::{
+    Canceller, ConnectionState, Delivery, Loopback, OutboxPolicy, SendError, ServerConfig,
+    WsClient, WsConnection,
+};
+
+use common::{
+    client_config, spawn_connection, start_handler, start_server, wait_until, within, Echo,
+    EchoMessage, Recorder,
+};
+
+// A client that starts out with no server to connect to.
+fn disconnected_client(loopback: &Loopback, policy: OutboxPolicy) -> (WsClient<Echo>, Canceller) {
+    let mut config = client_config();
+    config.outbox_policy = policy;
+
+    let mut connection = WsConnection::with_config(config);
+    let echo = connection.client::<Echo>();
+    (echo, spawn_connection(loopback, connection))
+}
+
+fn say(text: &str) -> EchoMessage {
+    EchoMessage::Say(text.into())
+}
+
+#[tokio::test]
+async fn drops_messages_sent_while_disconnected() {
+    let loopback = Loopback::new();
+    let (echo, _emitter) = disconnected_client(&loopback, OutboxPolicy::Drop);
+
+    assert_eq!(echo.try_send(say("lost")).unwrap(), Delivery::Dropped);
+
+    let _server = start_server(&loopback, ServerConfig::default());
+    let (mut messages, _handler) = start_handler(&loopback);
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    assert_eq!(echo.try_send(say("kept")).unwrap(), Delivery::Sent);
+    assert_eq!(within(messages.recv()).await.unwrap(), "kept");
+}
+
+#[tokio::test]
+async fn rejects_messages_sent_while_disconnected() {
+    let loopback = Loopback::new();
+    let (echo, _emitter) = disconnected_client(&loopback, OutboxPolicy::Reject);
+
+    assert!(matches!(
+        echo.try_send(say("refused")),
+        Err(SendError::NotConnected)
+    ));
+
+    let _server = start_server(&loopback, ServerConfig::default());
+    let (mut messages, _handler) = start_handler(&loopback);
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    assert_eq!(echo.try_send(say("accepted")).unwrap(), Delivery::Sent);
+    assert_eq!(within(messages.recv()).await.unwrap(), "accepted");
+}
+
+#[tokio::test]
+async fn queues_messages_sent_while_disconnected_until_it_connects() {
+    let loopback = Loopback::new();
+    let _server = start_server(&loopback, ServerConfig::default());
+    let (tx, mut messages) = mpsc::unbounded_channel();
+    let mut connection = WsConnection::with_config(client_config());
+    connection.handler(Recorder(tx));
+    let state = connection.connection_state();
+    let _handler = spawn_connection(&loopback, connection);
+    wait_until(state, ConnectionState::is_connected).await;
+
+    // The client's task hasn't run yet, so it hasn't connected.
+    let ttl = Duration::from_secs(60);
+    let (echo, _emitter) = disconnected_client(&loopback, OutboxPolicy::Queue { ttl });
+    assert_eq!(echo.try_send(say("waited")).unwrap(), Delivery::Queued);
+
+    assert_eq!(within(messages.recv()).await.unwrap(), "waited");
+}
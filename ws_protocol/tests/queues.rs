@@ -0,0 +1,201 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::{mpsc, Semaphore};
+use ws_protocol::{
+    ApiSpecifier, ClientApi, Delivery, DeliveryStatus, Handshake, Loopback, OverflowPolicy,
+    QueueLimits, QueueStats, Registration, SendError, ServerConfig, Transport, WsClient,
+    WsConnection, PROTOCOL_VERSION,
+};
+
+use common::{
+    client_config, spawn_connection, start_server, wait_until, within, Echo, EchoMessage, URL,
+};
+
+const SENT: usize = 50;
+
+// A handler that doesn't read from its socket until it's let go, so messages back up on the
+// server. The client library never stops reading, so this one speaks the protocol itself,
+// and connects again whenever the server drops it.
+fn stalled_handler(loopback: &Loopback, gate: Arc<Semaphore>, seen: mpsc::UnboundedSender<usize>) {
+    let loopback = loopback.clone();
+    tokio::spawn(async move {
+        loop {
+            let stream = match loopback.connect(URL).await {
+                Ok(stream) => stream,
+                Err(_) => {
+                    tokio::task::yield_now().await;
+                    continue;
+                }
+            };
+            let (mut ws, _) = tokio_tungstenite::client_async(URL, stream).await.unwrap();
+
+            ws.next().await.unwrap().unwrap();
+            let identification = Handshake::ClientIdentification {
+                apis: vec![Registration {
+                    api_specifier: ApiSpecifier::Handles("echo".into()),
+                    schema: Echo::schema(),
+                    replay: false,
+                }],
+                proof: None,
+                protocol_version: PROTOCOL_VERSION,
+                identity: None,
+                codecs: vec![],
+            };
+            ws.send(identification.into()).await.unwrap();
+            ws.next().await.unwrap().unwrap();
+
+            gate.acquire().await.unwrap().forget();
+            while let Some(Ok(frame)) = ws.next().await {
+                let Ok(message) =
+                    serde_json::from_str::<serde_json::Value>(frame.to_text().unwrap_or_default())
+                else {
+                    continue;
+                };
+                let Some(content) = message["content"].as_str() else {
+                    continue;
+                };
+                let EchoMessage::Say(text) = serde_json::from_str(content).unwrap();
+                let (index, _) = text.split_once(':').unwrap();
+                if seen.send(index.parse().unwrap()).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+struct Setup {
+    echo: WsClient<Echo>,
+    gate: Arc<Semaphore>,
+    seen: mpsc::UnboundedReceiver<usize>,
+    stats: Arc<QueueStats>,
+    _running: Vec<Box<dyn std::any::Any>>,
+}
+
+async fn setup(policy: OverflowPolicy) -> Setup {
+    let loopback = Loopback::new();
+    let server = start_server(
+        &loopback,
+        ServerConfig {
+            queue: QueueLimits {
+                capacity: 4,
+                policy,
+                ..QueueLimits::default()
+            },
+            ..ServerConfig::default()
+        },
+    );
+
+    let gate = Arc::new(Semaphore::new(0));
+    let (seen, seen_rx) = mpsc::unbounded_channel();
+    stalled_handler(&loopback, gate.clone(), seen);
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let emitter = spawn_connection(&loopback, connection);
+
+    wait_until(echo.peer_schemas(), |schemas| schemas.len() == 1).await;
+
+    Setup {
+        echo,
+        gate,
+        seen: seen_rx,
+        stats: server.queue_stats.clone(),
+        _running: vec![Box::new(server), Box::new(emitter)],
+    }
+}
+
+// Big enough that a stalled handler stops reading long before all of them are sent.
+fn message(index: usize) -> EchoMessage {
+    EchoMessage::Say(format!("{index}:{}", "x".repeat(16 * 1024)))
+}
+
+async fn flood(echo: &WsClient<Echo>) -> Vec<DeliveryStatus> {
+    let mut statuses = vec![];
+    for index in 0..SENT {
+        statuses.push(within(echo.send_confirmed(message(index))).await.unwrap());
+    }
+    statuses
+}
+
+#[tokio::test]
+async fn drops_the_newest_messages_for_a_slow_handler() {
+    let mut setup = setup(OverflowPolicy::DropNewest).await;
+
+    let statuses = flood(&setup.echo).await;
+    assert_eq!(statuses[0], DeliveryStatus::Delivered(1));
+    assert_eq!(statuses[SENT - 1], DeliveryStatus::Delivered(0));
+
+    let dropped = setup.stats.snapshot().dropped;
+    assert!(dropped > 0);
+    assert_eq!(setup.stats.snapshot().peak_depth, 4);
+
+    setup.gate.add_permits(SENT);
+    let delivered = SENT - dropped as usize;
+    for expected in 0..delivered {
+        assert_eq!(within(setup.seen.recv()).await.unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn drops_the_oldest_messages_for_a_slow_handler() {
+    let mut setup = setup(OverflowPolicy::DropOldest).await;
+
+    let statuses = flood(&setup.echo).await;
+    assert!(statuses
+        .iter()
+        .all(|status| *status == DeliveryStatus::Delivered(1)));
+    let dropped = setup.stats.snapshot().dropped;
+    assert!(dropped > 0);
+
+    setup.gate.add_permits(SENT);
+    let mut received = 0;
+    loop {
+        received += 1;
+        if within(setup.seen.recv()).await.unwrap() == SENT - 1 {
+            break;
+        }
+    }
+    assert_eq!(received, SENT - dropped as usize);
+}
+
+#[tokio::test]
+async fn disconnects_a_slow_handler() {
+    let setup = setup(OverflowPolicy::Disconnect).await;
+
+    flood(&setup.echo).await;
+    let stats = setup.stats.snapshot();
+    assert_eq!(stats.disconnected, 1);
+    assert_eq!(stats.depth, 0);
+
+    // Once it catches up, it reconnects and gets messages again.
+    setup.gate.add_permits(SENT);
+    within(async {
+        while setup.echo.send_confirmed(message(SENT)).await.unwrap()
+            != DeliveryStatus::Delivered(1)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn bounds_the_client_outbox() {
+    let mut config = client_config();
+    config.outbox_capacity = 2;
+    let mut connection = WsConnection::with_config(config);
+    let echo = connection.client::<Echo>();
+    let _never_connected = connection.build();
+
+    for _ in 0..2 {
+        let delivery = echo.try_send(EchoMessage::Say("hello".into()));
+        assert_eq!(delivery.unwrap(), Delivery::Queued);
+    }
+
+    let delivery = echo.try_send(EchoMessage::Say("hello".into()));
+    assert!(matches!(delivery, Err(SendError::OutboxFull)));
+}
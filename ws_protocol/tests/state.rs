@@ -0,0 +1,31 @@
+mod common;
+
+use ws_protocol::{ConnectionState, Loopback, ServerConfig, WsConnection};
+
+use common::{client_config, spawn_connection, start_server, wait_until, Echo};
+
+#[tokio::test]
+async fn reports_connection_state_as_the_server_comes_and_goes() {
+    let loopback = Loopback::new();
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let state = echo.connection_state();
+    let canceller = spawn_connection(&loopback, connection);
+    assert_eq!(*state.borrow(), ConnectionState::Connecting);
+
+    let server = start_server(&loopback, ServerConfig::default());
+    wait_until(state.clone(), ConnectionState::is_connected).await;
+
+    server.shutdown();
+    wait_until(state.clone(), |state| {
+        matches!(state, ConnectionState::Backoff(_))
+    })
+    .await;
+
+    let _server = start_server(&loopback, ServerConfig::default());
+    wait_until(state.clone(), ConnectionState::is_connected).await;
+
+    canceller.cancel();
+    wait_until(state, |state| *state == ConnectionState::Cancelled).await;
+}
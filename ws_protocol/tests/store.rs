@@ -0,0 +1,106 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ws_protocol::{DeliveryStatus, Loopback, Retention, ServerConfig, StoreConfig, WsConnection};
+
+use common::{
+    client_config, spawn_connection, start_handler, start_server, wait_until, within, Echo,
+    EchoMessage,
+};
+
+fn store_config(dir: &Path) -> StoreConfig {
+    StoreConfig {
+        dir: dir.into(),
+        retention: HashMap::from([(
+            "echo".to_string(),
+            Retention {
+                max_messages: 10,
+                max_age: Duration::from_secs(60),
+            },
+        )]),
+    }
+}
+
+fn store_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("bjorn-ws-{name}-{}", std::process::id()))
+}
+
+#[tokio::test]
+async fn delivers_stored_messages_once_a_handler_registers() {
+    let dir = store_dir("store");
+    let loopback = Loopback::new();
+    let _server = start_server(
+        &loopback,
+        ServerConfig {
+            store: Some(store_config(&dir)),
+            ..ServerConfig::default()
+        },
+    );
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+
+    for text in ["first", "second"] {
+        let status = within(echo.send_confirmed(EchoMessage::Say(text.into()))).await;
+        assert_eq!(status.unwrap(), DeliveryStatus::Stored);
+    }
+
+    let (mut messages, _handler) = start_handler(&loopback);
+    assert_eq!(within(messages.recv()).await.unwrap(), "first");
+    assert_eq!(within(messages.recv()).await.unwrap(), "second");
+
+    std::fs::remove_dir_all(dir).unwrap_or_default();
+}
+
+#[tokio::test]
+async fn delivers_stored_messages_after_the_server_restarts() {
+    let dir = store_dir("restart");
+    let loopback = Loopback::new();
+    let server = start_server(
+        &loopback,
+        ServerConfig {
+            store: Some(store_config(&dir)),
+            ..ServerConfig::default()
+        },
+    );
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let _emitter = spawn_connection(&loopback, connection);
+
+    let status = within(echo.send_confirmed(EchoMessage::Say("kept".into()))).await;
+    assert_eq!(status.unwrap(), DeliveryStatus::Stored);
+
+    server.shutdown();
+    wait_until(echo.connection_state(), |state| !state.is_connected()).await;
+
+    // The store is written in the background, so give it a moment to catch up.
+    let file = dir.join("echo.jsonl");
+    within(async {
+        while !std::fs::read_to_string(&file)
+            .unwrap_or_default()
+            .ends_with('\n')
+        {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await;
+
+    let _server = start_server(
+        &loopback,
+        ServerConfig {
+            store: Some(store_config(&dir)),
+            ..ServerConfig::default()
+        },
+    );
+    let (mut messages, _handler) = start_handler(&loopback);
+    assert_eq!(within(messages.recv()).await.unwrap(), "kept");
+
+    std::fs::remove_dir_all(dir).unwrap_or_default();
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use std::path::PathBuf;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use sha2::{Digest, Sha256};
+use ws_protocol::{
+    Canceller, ClientTlsConfig, ConnectionState, Loopback, ServerConfig, ServerTlsConfig, WsClient,
+    WsConnection, WsTask,
+};
+
+use common::{client_config, start_server, wait_until, Echo};
+
+const TLS_URL: &str = "wss://bjorn:1";
+
+struct Certs {
+    dir: PathBuf,
+    ca_path: String,
+    cert_path: String,
+    key_path: String,
+    fingerprint: String,
+}
+
+impl Drop for Certs {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).unwrap_or_default();
+    }
+}
+
+// A CA and a certificate it issued for the loopback server's host name.
+fn issue_certs(name: &str) -> Certs {
+    let dir = std::env::temp_dir().join(format!("bjorn-ws-tls-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut ca_params = CertificateParams::new(vec![]);
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params).unwrap();
+    let cert = Certificate::from_params(CertificateParams::new(vec!["bjorn".into()])).unwrap();
+
+    let write = |file: &str, contents: String| {
+        let path = dir.join(file);
+        std::fs::write(&path, contents).unwrap();
+        path.display().to_string()
+    };
+
+    let cert_pem = cert.serialize_pem_with_signer(&ca).unwrap();
+    let der = rustls_pemfile::certs(&mut cert_pem.as_bytes())
+        .unwrap()
+        .remove(0);
+
+    Certs {
+        ca_path: write("ca.pem", ca.serialize_pem().unwrap()),
+        cert_path: write("cert.pem", cert_pem),
+        key_path: write("key.pem", cert.serialize_private_key_pem()),
+        fingerprint: hex::encode(Sha256::digest(der)),
+        dir,
+    }
+}
+
+fn start_tls_server(loopback: &Loopback, certs: &Certs) -> impl Sized {
+    start_server(
+        loopback,
+        ServerConfig {
+            tls: Some(ServerTlsConfig {
+                cert_path: certs.cert_path.clone(),
+                key_path: certs.key_path.clone(),
+            }),
+            ..ServerConfig::default()
+        },
+    )
+}
+
+fn connect(
+    loopback: &Loopback,
+    tls: ClientTlsConfig,
+    max_attempts: Option<u32>,
+) -> (WsClient<Echo>, Canceller) {
+    let mut config = client_config();
+    config.tls = tls;
+    config.reconnect.max_attempts = max_attempts;
+
+    let mut connection = WsConnection::with_config(config);
+    let echo = connection.client::<Echo>();
+    let (runner, canceller) = connection.build();
+    tokio::spawn(runner.with_transport(loopback.clone()).run(TLS_URL.into()));
+
+    (echo, canceller)
+}
+
+fn trusting(certs: &Certs) -> ClientTlsConfig {
+    ClientTlsConfig {
+        ca_path: Some(certs.ca_path.clone()),
+        pinned_cert_sha256: None,
+    }
+}
+
+#[tokio::test]
+async fn connects_to_a_server_signed_by_a_trusted_ca() {
+    let certs = issue_certs("ca");
+    let loopback = Loopback::new();
+    let _server = start_tls_server(&loopback, &certs);
+
+    let (echo, _canceller) = connect(&loopback, trusting(&certs), None);
+    wait_until(echo.connection_state(), ConnectionState::is_connected).await;
+}
+
+#[tokio::test]
+async fn connects_to_a_server_with_the_pinned_certificate() {
+    let certs = issue_certs("pinned");
+    let loopback = Loopback::new();
+    let _server = start_tls_server(&loopback, &certs);
+
+    let tls = ClientTlsConfig {
+        ca_path: None,
+        pinned_cert_sha256: Some(certs.fingerprint.clone()),
+    };
+    let (echo, _canceller) = connect(&loopback, tls, None);
+    wait_until(echo.connection_state(), ConnectionState::is_connected).await;
+}
+
+#[tokio::test]
+async fn refuses_a_server_that_does_not_match_the_pin() {
+    let certs = issue_certs("mismatch");
+    let loopback = Loopback::new();
+    let _server = start_tls_server(&loopback, &certs);
+
+    // Once a trusting client is in, the server is up for the next one to try.
+    let (trusted, _trusted) = connect(&loopback, trusting(&certs), None);
+    wait_until(trusted.connection_state(), ConnectionState::is_connected).await;
+
+    let tls = ClientTlsConfig {
+        ca_path: None,
+        pinned_cert_sha256: Some(hex::encode([0; 32])),
+    };
+    let (echo, _canceller) = connect(&loopback, tls, Some(1));
+    wait_until(echo.connection_state(), |state| {
+        *state == ConnectionState::GaveUp
+    })
+    .await;
+}
@@ -0,0 +1,44 @@
+#![cfg(unix)]
+
+mod common;
+
+use tokio::sync::mpsc;
+use ws_protocol::{ServerConfig, WsConnection, WsServer, WsTask};
+
+use common::{client_config, wait_until, within, Echo, EchoMessage, Recorder};
+
+#[tokio::test]
+async fn serves_clients_over_a_unix_socket() {
+    let path = std::env::temp_dir().join(format!("bjorn-ws-{}.sock", std::process::id()));
+    let addr = format!("unix://{}", path.display());
+
+    let (runner, _server) = WsServer::new(ServerConfig {
+        unix_socket_mode: Some(0o600),
+        ..ServerConfig::default()
+    });
+    tokio::spawn(runner.run(addr.clone()));
+
+    let (tx, mut messages) = mpsc::unbounded_channel();
+    let mut handler = WsConnection::with_config(client_config());
+    handler.handler(Recorder(tx));
+    let (runner, _handler) = handler.build();
+    tokio::spawn(runner.run(addr.clone()));
+
+    let mut connection = WsConnection::with_config(client_config());
+    let echo = connection.client::<Echo>();
+    let (runner, _canceller) = connection.build();
+    tokio::spawn(runner.run(addr));
+
+    wait_until(echo.peer_schemas(), |schemas| !schemas.is_empty()).await;
+
+    echo.send(EchoMessage::Say("hello".into()));
+    assert_eq!(within(messages.recv()).await.unwrap(), "hello");
+
+    let mode = std::os::unix::fs::PermissionsExt::mode(&path.metadata().unwrap().permissions());
+    assert_eq!(mode & 0o777, 0o600);
+
+    // The private directory it was bound in is gone.
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let bound_in = path.with_file_name(format!(".{file_name}.{}", std::process::id()));
+    assert!(!bound_in.exists());
+}
@@ -0,0 +1,29 @@
+// Kept apart from ws_protocol, which doesn't build for wasm, so the web dashboard can share it.
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+pub const PROTOCOL_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerIdentification {
+    pub challenge: String,
+    #[serde(default)]
+    pub protocol_version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObserverIdentification {
+    pub subscriptions: Vec<String>,
+    pub proof: Option<String>,
+    #[serde(default)]
+    pub protocol_version: u32,
+}
+
+// The answer to a server's challenge, which shows the secret is known without sending it.
+pub fn prove(secret: &str, challenge: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key size");
+    mac.update(challenge.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
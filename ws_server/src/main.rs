@@ -4,14 +4,21 @@ use std::{env, error::Error};
 async fn main() -> Result<(), Box<dyn Error>> {
     let addr = env::var("BJORN_WS_LISTEN_ADDRESS")?;
 
-    let (runner, canceller) = ws_protocol::WsServer::new();
+    // Clients retry quickly for a while after a restart, so only say so when the server is
+    // about to come straight back, e.g. during a deploy.
+    let restart = env::var("BJORN_WS_ON_SIGINT").as_deref() == Ok("restart");
+
+    let (runner, canceller) = ws_protocol::WsServer::new(ws_protocol::ServerConfig::from_env());
 
     let mut canceller = Some(canceller);
     ctrlc::set_handler(move || {
         println!("^C");
 
         if let Some(canceller) = canceller.take() {
-            canceller.cancel();
+            match restart {
+                true => canceller.restart(),
+                false => canceller.shutdown(),
+            }
         }
     })
     .expect("Ctrl+C");